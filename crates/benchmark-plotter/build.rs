@@ -1,8 +0,0 @@
-use std::error::Error;
-use vergen::EmitBuilder;
-
-fn main() -> Result<(), Box<dyn Error>> {
-    // Emit the instructions
-    EmitBuilder::builder().all_git().emit()?;
-    Ok(())
-}
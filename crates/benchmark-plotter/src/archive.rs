@@ -0,0 +1,297 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Read,
+    process::{Command, Stdio},
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, FixedOffset};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::git::CommitInfo;
+use crate::history::GENERATOR;
+use crate::json::{read_json_from_str, BenchFile, IdPattern, Statistic};
+
+// Metadata files archived next to the bench JSON. Archives made by the deploy workflow before the
+// plotter wrote them only have the labels and `Cargo.lock`
+const MANIFEST_FILE: &str = "manifest.json";
+const LABELS_FILE: &str = "labels.md";
+const CARGO_LOCK_FILE: &str = "Cargo.lock";
+
+// Describes the run an archive was made from, so its results can be told apart and reproduced
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Manifest {
+    // Full SHA of the benchmarked commit
+    pub commit: String,
+    pub commit_date: Option<DateTime<FixedOffset>>,
+    // Labels of the runner, e.g. `self-hosted gpu-bench`
+    pub labels: Option<String>,
+    // One line per GPU as reported by `nvidia-smi`, empty when there's none
+    #[serde(default)]
+    pub gpus: Vec<String>,
+    // Env vars configuring the benchmarks, e.g. `LURK_RC`
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    // `rustc --version` in the benchmarked repository, which respects its `rust-toolchain.toml`
+    pub toolchain: Option<String>,
+    // Name and version of the plotter that wrote the archive
+    pub generator: String,
+}
+
+impl Manifest {
+    // Describes the current machine and environment. `env` holds the names of env vars to record
+    // besides those starting with `<env_prefix>_`. Missing tools are recorded as unknown rather
+    // than failing, since the archive is still useful without them
+    pub fn collect(
+        commit: &CommitInfo,
+        labels: Option<String>,
+        env_prefix: &str,
+        env: &[String],
+        repo: &Utf8Path,
+    ) -> Self {
+        let prefix = format!("{env_prefix}_");
+        let env = std::env::vars()
+            .filter(|(name, _)| name.starts_with(&prefix) || env.contains(name))
+            .collect();
+        let gpus = command_output(
+            Command::new("nvidia-smi")
+                .arg("--query-gpu=name,driver_version,memory.total,compute_cap")
+                .arg("--format=csv,noheader"),
+        )
+        .map(|gpus| gpus.lines().map(str::to_owned).collect())
+        .unwrap_or_default();
+        let toolchain = command_output(Command::new("rustc").arg("--version").current_dir(repo));
+        Self {
+            commit: commit.sha.clone(),
+            commit_date: commit.date,
+            labels,
+            gpus,
+            env,
+            toolchain,
+            generator: GENERATOR.to_owned(),
+        }
+    }
+}
+
+// The trimmed stdout of a command, if it could be run and succeeded
+fn command_output(command: &mut Command) -> Option<String> {
+    let output = command.stderr(Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+// The contents of a per-run `<bench>-<short-sha>.tar.gz` archive
+#[derive(Debug)]
+pub struct Archive {
+    pub path: Utf8PathBuf,
+    // Bench JSON files in the archive, sorted by name
+    pub bench_files: Vec<BenchFile>,
+    // Runner labels of the run
+    pub labels: Option<String>,
+    // Whether the archive has the `Cargo.lock` the benchmark was built with
+    pub cargo_lock: bool,
+    pub manifest: Option<Manifest>,
+    // Metadata files that couldn't be read, e.g. a malformed manifest. Like malformed records,
+    // they're skipped so the archive's results can still be used
+    pub skipped: Vec<Error>,
+}
+
+// Reads a gzipped tarball in-process. Bench JSON files are read from any directory in it, and
+// other files are ignored
+pub fn read_archive(
+    path: &Utf8Path,
+    pattern: &IdPattern,
+    statistic: Statistic,
+) -> Result<Archive, Error> {
+    let file = File::open(path).map_err(|e| Error::io(path, e))?;
+    let mut tar = tar::Archive::new(GzDecoder::new(file));
+    let mut archive = Archive {
+        path: path.to_owned(),
+        bench_files: vec![],
+        labels: None,
+        cargo_lock: false,
+        manifest: None,
+        skipped: vec![],
+    };
+
+    for entry in tar.entries().map_err(|e| Error::io(path, e))? {
+        let mut entry = entry.map_err(|e| Error::io(path, e))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path().map_err(|e| Error::io(path, e))?;
+        let name = Utf8PathBuf::from(name.to_string_lossy().trim_start_matches("./"));
+        let is_json = name.extension() == Some("json");
+        match name.file_name() {
+            Some(MANIFEST_FILE) => match serde_json::from_reader(&mut entry) {
+                Ok(manifest) => archive.manifest = Some(manifest),
+                Err(e) if e.is_io() => return Err(Error::io(path.join(&name), e.into())),
+                Err(e) => archive.skipped.push(Error::serde(path.join(&name), e)),
+            },
+            Some(LABELS_FILE) => {
+                let mut labels = String::new();
+                entry
+                    .read_to_string(&mut labels)
+                    .map_err(|e| Error::io(path.join(&name), e))?;
+                archive.labels = Some(labels.trim().to_owned());
+            }
+            Some(CARGO_LOCK_FILE) => archive.cargo_lock = true,
+            _ if is_json => {
+                let mut json = String::new();
+                entry
+                    .read_to_string(&mut json)
+                    .map_err(|e| Error::io(path.join(&name), e))?;
+                // Errors point into the archive, e.g. `fibonacci-abc1234.tar.gz/fibonacci-abc1234.json`
+                let bench_file = read_json_from_str(&path.join(&name), &json, pattern, statistic);
+                archive.bench_files.push(bench_file);
+            }
+            _ => {}
+        }
+    }
+    archive.bench_files.sort_by(|a, b| a.path.cmp(&b.path));
+    if let Some(manifest) = &archive.manifest {
+        archive.labels = archive.labels.or_else(|| manifest.labels.clone());
+    }
+    Ok(archive)
+}
+
+// Writes a gzipped tarball of the manifest, `Cargo.lock` and the bench files, with the labels also
+// in `labels.md` for plotters that predate the manifest. Entries are stamped with the commit date
+// rather than the current time, so archiving the same run twice gives the same bytes
+pub fn write_archive(
+    path: &Utf8Path,
+    bench_files: &[Utf8PathBuf],
+    cargo_lock: &Utf8Path,
+    manifest: &Manifest,
+) -> Result<(), Error> {
+    let mtime = manifest
+        .commit_date
+        .map_or(0, |date| date.timestamp().max(0) as u64);
+    let file = File::create(path).map_err(|e| Error::io(path, e))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let mut append = |name: &str, data: &[u8]| {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        tar.append_data(&mut header, name, data)
+            .map_err(|e| Error::io(path, e))
+    };
+
+    let manifest_json = serde_json::to_vec_pretty(manifest).map_err(|e| Error::serde(path, e))?;
+    append(MANIFEST_FILE, &manifest_json)?;
+    if let Some(labels) = &manifest.labels {
+        append(LABELS_FILE, format!("{labels}\n").as_bytes())?;
+    }
+    let lock = std::fs::read(cargo_lock).map_err(|e| Error::io(cargo_lock, e))?;
+    append(CARGO_LOCK_FILE, &lock)?;
+    for bench_file in bench_files {
+        let name = bench_file.file_name().unwrap_or(bench_file.as_str());
+        let data = std::fs::read(bench_file).map_err(|e| Error::io(bench_file, e))?;
+        append(name, &data)?;
+    }
+
+    tar.into_inner()
+        .and_then(|gz| gz.finish())
+        .map_err(|e| Error::io(path, e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BENCH_JSON: &str = r#"{"reason":"benchmark-complete","id":"Fibonacci-num=10/28db40f-2024-01-30T19_07_04Z/rc=100","typical":{"estimate":15000.0,"lower_bound":14700.0,"upper_bound":15300.0,"unit":"ns"}}
+{"reason":"group-complete"}
+{"reason":"benchmark-complete","id":"Fibonacci-num=10/28db40f-2024-01-30T19_07_04Z/rc=200","typical":{"estimate":17500.0,"lower_bound":17150.0,"upper_bound":17850.0,"unit":"ns"}}
+"#;
+
+    fn manifest(labels: Option<&str>) -> Manifest {
+        Manifest {
+            commit: "28db40f0c0ffee".into(),
+            commit_date: Some("2024-01-30T19:07:04Z".parse().unwrap()),
+            labels: labels.map(str::to_owned),
+            gpus: vec!["NVIDIA A10, 535.104.05, 23028 MiB, 8.6".into()],
+            env: [("LURK_RC".to_owned(), "100".to_owned())].into(),
+            toolchain: Some("rustc 1.75.0".into()),
+            generator: GENERATOR.to_owned(),
+        }
+    }
+
+    #[test]
+    fn reads_back_written_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let bench_file = dir.join("fibonacci-28db40f.json");
+        std::fs::write(&bench_file, BENCH_JSON).unwrap();
+        let cargo_lock = dir.join("Cargo.lock");
+        std::fs::write(&cargo_lock, "version = 3\n").unwrap();
+
+        let path = dir.join("fibonacci-28db40f.tar.gz");
+        let written = manifest(Some("self-hosted gpu-bench"));
+        write_archive(
+            &path,
+            std::slice::from_ref(&bench_file),
+            &cargo_lock,
+            &written,
+        )
+        .unwrap();
+        let archive = read_archive(&path, &IdPattern::default(), Statistic::Typical).unwrap();
+
+        let read = archive.manifest.unwrap();
+        assert_eq!(read.commit, written.commit);
+        assert_eq!(read.commit_date, written.commit_date);
+        assert_eq!((&read.gpus, &read.env), (&written.gpus, &written.env));
+        assert_eq!(read.toolchain, written.toolchain);
+        assert_eq!(archive.labels.as_deref(), Some("self-hosted gpu-bench"));
+        assert!(archive.cargo_lock);
+        assert!(archive.skipped.is_empty());
+
+        let [file] = &archive.bench_files[..] else {
+            panic!("expected one bench file, got {:?}", archive.bench_files);
+        };
+        assert_eq!(file.path, path.join("fibonacci-28db40f.json"));
+        assert!(file.skipped.is_empty());
+        let ids = file
+            .data
+            .iter()
+            .map(|bench| (bench.id.group_name.as_str(), bench.id.params.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [
+                ("Fibonacci-num=10", "rc=100"),
+                ("Fibonacci-num=10", "rc=200")
+            ]
+        );
+
+        // Entries are stamped with the commit date, so archiving the run again gives the same bytes
+        let again = dir.join("again.tar.gz");
+        write_archive(&again, &[bench_file], &cargo_lock, &written).unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            std::fs::read(&again).unwrap()
+        );
+    }
+
+    #[test]
+    fn archives_without_labels() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let cargo_lock = dir.join("Cargo.lock");
+        std::fs::write(&cargo_lock, "version = 3\n").unwrap();
+
+        let path = dir.join("empty.tar.gz");
+        write_archive(&path, &[], &cargo_lock, &manifest(None)).unwrap();
+        let archive = read_archive(&path, &IdPattern::default(), Statistic::Typical).unwrap();
+        assert_eq!(archive.labels, None);
+        assert!(archive.manifest.is_some());
+        assert!(archive.bench_files.is_empty());
+    }
+}
@@ -0,0 +1,485 @@
+use std::borrow::Borrow;
+
+use chrono::{DateTime, Utc};
+use clap::Args;
+use serde::Serialize;
+
+use crate::json::{BenchData, Statistic};
+use crate::plot::{Plot, Plots, Point};
+use crate::unit::Scale;
+
+// Options for comparing the newest point of each line to its recent history
+#[derive(Args, Debug, Clone)]
+pub struct DetectOptions {
+    /// Number of points before the newest one whose median is used as the baseline
+    #[arg(long, default_value_t = DetectOptions::DEFAULT_WINDOW)]
+    pub window: usize,
+
+    /// Change from the baseline, in percent, beyond which a result counts as a regression or
+    /// improvement. The newest point's confidence interval must also exclude the baseline
+    #[arg(long, default_value_t = DetectOptions::DEFAULT_THRESHOLD)]
+    pub threshold: f64,
+}
+
+impl DetectOptions {
+    const DEFAULT_WINDOW: usize = 5;
+    const DEFAULT_THRESHOLD: f64 = 10.0;
+}
+
+impl Default for DetectOptions {
+    fn default() -> Self {
+        Self {
+            window: Self::DEFAULT_WINDOW,
+            threshold: Self::DEFAULT_THRESHOLD,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Regression,
+    Improvement,
+    Unchanged,
+    // No earlier result to compare with
+    New,
+}
+
+// How the newest point of a line compares to its baseline, in the plot's stored unit
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub group: String,
+    pub params: String,
+    pub commit: Option<String>,
+    pub date: DateTime<Utc>,
+    pub unit: String,
+    pub value: f64,
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+    pub baseline: Option<f64>,
+    // Confidence interval of the baseline, only known when it's a single result
+    pub baseline_lower: Option<f64>,
+    pub baseline_upper: Option<f64>,
+    // Number of points the baseline was computed from
+    pub baseline_points: usize,
+    // Relative change from the baseline in percent, positive meaning slower
+    pub change: Option<f64>,
+    pub kind: ChangeKind,
+}
+
+impl Change {
+    // Whether the change should be pointed out, i.e. is a regression or improvement
+    pub fn is_significant(&self) -> bool {
+        matches!(self.kind, ChangeKind::Regression | ChangeKind::Improvement)
+    }
+
+    // The benchmark's group and params, e.g. `Fibonacci-num=10 rc=100`
+    pub fn name(&self) -> String {
+        if self.params.is_empty() {
+            self.group.clone()
+        } else {
+            format!("{} {}", self.group, self.params)
+        }
+    }
+
+    // The scale to show both the value and the baseline in
+    pub fn scale(&self) -> Scale {
+        Scale::readable(
+            &self.unit,
+            self.value.max(self.baseline.unwrap_or_default()),
+        )
+    }
+
+    // Range of the relative change in percent, from the confidence intervals of both results
+    pub fn change_interval(&self) -> Option<(f64, f64)> {
+        let (lower, upper) = (self.lower?, self.upper?);
+        let (baseline_lower, baseline_upper) = (self.baseline_lower?, self.baseline_upper?);
+        if baseline_lower == 0.0 || baseline_upper == 0.0 {
+            return None;
+        }
+        Some((
+            (lower - baseline_upper) / baseline_upper * 100.0,
+            (upper - baseline_lower) / baseline_lower * 100.0,
+        ))
+    }
+
+    // One line description in a readable unit, e.g.
+    // `Regression in Fibonacci-num=10 rc=100 at 28db40f: 1.200 ms vs baseline 1.000 ms (+20.00%)`
+    pub fn describe(&self) -> String {
+        let scale = self.scale();
+        let kind = match self.kind {
+            ChangeKind::Regression => "Regression",
+            ChangeKind::Improvement => "Improvement",
+            ChangeKind::Unchanged => "No change",
+            ChangeKind::New => "New benchmark",
+        };
+        let at = self.commit.as_deref().map(|commit| format!(" at {commit}"));
+        let mut text = format!(
+            "{kind} in {}{}: {:.3} {}",
+            self.name(),
+            at.unwrap_or_default(),
+            self.value * scale.factor,
+            scale.unit,
+        );
+        if let (Some(baseline), Some(change)) = (self.baseline, self.change) {
+            text.push_str(&format!(
+                " vs baseline {:.3} {} ({change:+.2}%",
+                baseline * scale.factor,
+                scale.unit
+            ));
+            if let Some((lower, upper)) = self.change_interval() {
+                text.push_str(&format!(" [{lower:+.2}%, {upper:+.2}%]"));
+            }
+            text.push(')');
+        }
+        text
+    }
+}
+
+// Compares the newest point of every line to the median of up to `window` points before it,
+// sorted by group and params. Only lines with a point of the plot's newest commit are compared, so
+// an old result isn't reported again by every later run. Lines with a single point have no
+// baseline and are left out
+pub fn detect(plots: &Plots, options: &DetectOptions) -> Vec<Change> {
+    let mut changes = vec![];
+    for (group, plot) in plots.iter() {
+        for (params, points) in plot.current_lines() {
+            let Some((newest, history)) = points.split_last() else {
+                continue;
+            };
+            if history.is_empty() {
+                continue;
+            }
+            let window = &history[history.len().saturating_sub(options.window)..];
+            changes.push(compare(group, params, &plot.unit, newest, window, options));
+        }
+    }
+    changes.sort_by(|a, b| (&a.group, &a.params).cmp(&(&b.group, &b.params)));
+    changes
+}
+
+// Compares new benchmark results, e.g. from a PR, to the median of up to `window` earlier points
+// of their line in the history. Points of the same commit are left out of the baseline, so
+// results that were already ingested aren't compared with themselves
+pub fn compare_with_history(
+    plots: &Plots,
+    bench_data: &[BenchData],
+    statistic: Statistic,
+    options: &DetectOptions,
+) -> Vec<Change> {
+    let mut changes = vec![];
+    for bench in bench_data {
+        let Some(estimate) = bench.result.estimate(statistic) else {
+            continue;
+        };
+        let new_plot;
+        let plot = match plots.get(&bench.id.group_name) {
+            Some(plot) => plot,
+            None => {
+                new_plot = Plot::new(&estimate.unit);
+                &new_plot
+            }
+        };
+        let point = plot.point(bench, estimate);
+        let history = plot
+            .lines
+            .get(&bench.id.params)
+            .into_iter()
+            .flatten()
+            .filter(|p| point.commit.is_none() || p.commit != point.commit)
+            .collect::<Vec<_>>();
+        let window = &history[history.len().saturating_sub(options.window)..];
+        changes.push(compare(
+            &bench.id.group_name,
+            &bench.id.params,
+            &plot.unit,
+            &point,
+            window,
+            options,
+        ));
+    }
+    changes.sort_by(|a, b| (&a.group, &a.params).cmp(&(&b.group, &b.params)));
+    changes
+}
+
+// Compares the results of two benchmark runs, e.g. of a PR and its base branch. Results are
+// matched by group and params, ignoring the commit and date in their bench IDs, and each head
+// result is compared with the matching base result's confidence interval
+pub fn compare_runs(
+    base: &[BenchData],
+    head: &[BenchData],
+    statistic: Statistic,
+    options: &DetectOptions,
+) -> Vec<Change> {
+    let mut changes = vec![];
+    for bench in head {
+        let Some(estimate) = bench.result.estimate(statistic) else {
+            continue;
+        };
+        let plot = Plot::new(&estimate.unit);
+        let window = base
+            .iter()
+            .filter(|b| b.id.group_name == bench.id.group_name && b.id.params == bench.id.params)
+            .filter_map(|b| Some(plot.point(b, b.result.estimate(statistic)?)))
+            .collect::<Vec<_>>();
+        changes.push(compare(
+            &bench.id.group_name,
+            &bench.id.params,
+            &plot.unit,
+            &plot.point(bench, estimate),
+            &window,
+            options,
+        ));
+    }
+    changes.sort_by(|a, b| (&a.group, &a.params).cmp(&(&b.group, &b.params)));
+    changes
+}
+
+fn compare(
+    group: &str,
+    params: &str,
+    unit: &str,
+    newest: &Point,
+    window: &[impl Borrow<Point>],
+    options: &DetectOptions,
+) -> Change {
+    let baseline = median(window);
+    let (baseline_lower, baseline_upper) = match window {
+        [p] => (p.borrow().lower, p.borrow().upper),
+        _ => (None, None),
+    };
+    let change = baseline.map(|baseline| {
+        if baseline != 0.0 {
+            (newest.y - baseline) / baseline * 100.0
+        } else {
+            0.0
+        }
+    });
+    let kind = match (baseline, change) {
+        // The confidence intervals mustn't overlap. Without them, only the threshold applies
+        (Some(baseline), Some(change))
+            if change > options.threshold
+                && newest
+                    .lower
+                    .map_or(true, |l| l > baseline_upper.unwrap_or(baseline)) =>
+        {
+            ChangeKind::Regression
+        }
+        (Some(baseline), Some(change))
+            if change < -options.threshold
+                && newest
+                    .upper
+                    .map_or(true, |u| u < baseline_lower.unwrap_or(baseline)) =>
+        {
+            ChangeKind::Improvement
+        }
+        (Some(_), _) => ChangeKind::Unchanged,
+        (None, _) => ChangeKind::New,
+    };
+    Change {
+        group: group.to_owned(),
+        params: params.to_owned(),
+        commit: newest.commit.clone(),
+        date: newest.x,
+        unit: unit.to_owned(),
+        value: newest.y,
+        lower: newest.lower,
+        upper: newest.upper,
+        baseline,
+        baseline_lower,
+        baseline_upper,
+        baseline_points: window.len(),
+        change,
+        kind,
+    }
+}
+
+fn median(points: &[impl Borrow<Point>]) -> Option<f64> {
+    let mut values = points.iter().map(|p| p.borrow().y).collect::<Vec<_>>();
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    match values.len() {
+        0 => None,
+        len if len % 2 == 0 => Some((values[mid - 1] + values[mid]) / 2.0),
+        _ => Some(values[mid]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plot::Record;
+
+    fn point(y: f64, interval: Option<(f64, f64)>) -> Point {
+        serde_json::from_value(serde_json::json!({
+            "x": "2024-01-30T19:07:04Z",
+            "y": y,
+            "lower": interval.map(|(lower, _)| lower),
+            "upper": interval.map(|(_, upper)| upper),
+        }))
+        .unwrap()
+    }
+
+    fn points(values: &[f64]) -> Vec<Point> {
+        values.iter().map(|&y| point(y, None)).collect()
+    }
+
+    #[test]
+    fn median_of_windows() {
+        let cases: &[(&[f64], Option<f64>)] = &[
+            (&[], None),
+            (&[3.0], Some(3.0)),
+            (&[3.0, 1.0, 2.0], Some(2.0)),
+            (&[4.0, 1.0, 3.0, 2.0], Some(2.5)),
+            (&[1.0, 100.0], Some(50.5)),
+        ];
+        for (values, expected) in cases {
+            assert_eq!(median(&points(values)), *expected, "window {values:?}");
+        }
+    }
+
+    #[test]
+    fn classifies_changes() {
+        use ChangeKind::*;
+        // (case, baseline window, newest point, expected kind, expected change in percent)
+        let cases = [
+            ("no history", vec![], point(1.0, None), New, None),
+            (
+                "within threshold",
+                points(&[100.0]),
+                point(105.0, None),
+                Unchanged,
+                Some(5.0),
+            ),
+            (
+                "slower",
+                points(&[100.0]),
+                point(120.0, None),
+                Regression,
+                Some(20.0),
+            ),
+            (
+                "faster",
+                points(&[100.0]),
+                point(80.0, None),
+                Improvement,
+                Some(-20.0),
+            ),
+            (
+                "even window median",
+                points(&[120.0, 90.0, 110.0, 100.0]),
+                point(126.0, None),
+                Regression,
+                Some(20.0),
+            ),
+            (
+                "zero baseline",
+                points(&[0.0, 0.0]),
+                point(5.0, None),
+                Unchanged,
+                Some(0.0),
+            ),
+            (
+                "interval overlaps baseline",
+                points(&[100.0]),
+                point(120.0, Some((95.0, 140.0))),
+                Unchanged,
+                Some(20.0),
+            ),
+            (
+                "interval clears baseline",
+                points(&[100.0]),
+                point(120.0, Some((115.0, 125.0))),
+                Regression,
+                Some(20.0),
+            ),
+            (
+                "intervals overlap",
+                vec![point(100.0, Some((90.0, 118.0)))],
+                point(120.0, Some((115.0, 125.0))),
+                Unchanged,
+                Some(20.0),
+            ),
+            (
+                "intervals clear",
+                vec![point(100.0, Some((95.0, 105.0)))],
+                point(80.0, Some((75.0, 85.0))),
+                Improvement,
+                Some(-20.0),
+            ),
+        ];
+        let options = DetectOptions::default();
+        for (name, window, newest, kind, change) in cases {
+            let result = compare("Fibonacci", "", "ns", &newest, &window, &options);
+            assert_eq!(result.kind, kind, "{name}");
+            let rounded = result.change.map(|c| (c * 1e6).round() / 1e6);
+            assert_eq!(rounded, change, "{name}");
+            assert_eq!(result.baseline_points, window.len(), "{name}");
+        }
+    }
+
+    #[test]
+    fn keeps_the_interval_of_a_single_baseline_point() {
+        let options = DetectOptions::default();
+        let newest = point(120.0, Some((115.0, 125.0)));
+        let change = compare(
+            "Fibonacci",
+            "rc=100",
+            "ns",
+            &newest,
+            &[point(100.0, Some((95.0, 105.0)))],
+            &options,
+        );
+        assert_eq!(change.baseline, Some(100.0));
+        assert_eq!(
+            (change.baseline_lower, change.baseline_upper),
+            (Some(95.0), Some(105.0))
+        );
+        let (lower, upper) = change.change_interval().unwrap();
+        assert!((lower - (115.0 - 105.0) / 105.0 * 100.0).abs() < 1e-9);
+        assert!((upper - (125.0 - 95.0) / 95.0 * 100.0).abs() < 1e-9);
+
+        let change = compare(
+            "Fibonacci",
+            "rc=100",
+            "ns",
+            &newest,
+            &points(&[90.0, 100.0]),
+            &options,
+        );
+        assert_eq!((change.baseline_lower, change.baseline_upper), (None, None));
+        assert_eq!(change.change_interval(), None);
+    }
+
+    #[test]
+    fn only_compares_lines_of_the_newest_commit() {
+        let record = |commit: &str, seq: u64, params: &str, y: f64| -> Record {
+            serde_json::from_value(serde_json::json!({
+                "group": "Fib",
+                "params": params,
+                "unit": "ns",
+                "x": format!("2024-01-{:02}T00:00:00Z", seq),
+                "y": y,
+                "commit": commit,
+                "seq": seq,
+            }))
+            .unwrap()
+        };
+        let mut plots = Plots::new();
+        plots.add_records(&[
+            record("aaaaaaa", 1, "rc=100", 10.0),
+            record("aaaaaaa", 1, "rc=200", 20.0),
+            record("bbbbbbb", 2, "rc=200", 40.0),
+        ]);
+        let changes = detect(&plots, &DetectOptions::default());
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].kind, ChangeKind::Regression);
+
+        // The regression isn't reported again once a later commit didn't benchmark its line
+        plots.add_records(&[record("ccccccc", 3, "rc=100", 10.0)]);
+        let changes = detect(&plots, &DetectOptions::default());
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].params, "rc=100");
+        assert_eq!(changes[0].kind, ChangeKind::Unchanged);
+    }
+}
@@ -0,0 +1,119 @@
+use std::io;
+
+use camino::Utf8PathBuf;
+use thiserror::Error;
+
+use crate::json::{JsonError, Statistic};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to access {path}: {source}")]
+    Io {
+        path: Utf8PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to read or write JSON for {path}: {source}")]
+    Serde {
+        path: Utf8PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Invalid benchmark record in {path} at byte {}: {source}", source.offset)]
+    Json {
+        path: Utf8PathBuf,
+        #[source]
+        source: JsonError,
+    },
+    #[error("Invalid bench ID `{id}` in {path} at byte {offset}: {reason}")]
+    BenchId {
+        path: Utf8PathBuf,
+        offset: usize,
+        id: String,
+        reason: String,
+    },
+    #[error("Invalid timestamp `{input}` in {path} at byte {offset}: {source}")]
+    Timestamp {
+        path: Utf8PathBuf,
+        offset: usize,
+        input: String,
+        #[source]
+        source: chrono::ParseError,
+    },
+    #[error("Benchmark `{id}` in {path} at byte {offset} has no `{statistic}` estimate")]
+    Statistic {
+        path: Utf8PathBuf,
+        offset: usize,
+        id: String,
+        statistic: Statistic,
+    },
+    #[error("Failed to read plot data from {path}: {source}")]
+    History {
+        path: Utf8PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error(
+        "Plot data in {path} has version {version}, but only up to {supported} is supported. \
+         Update the plotter to read it"
+    )]
+    HistoryVersion {
+        path: Utf8PathBuf,
+        version: u64,
+        supported: u64,
+    },
+    #[error("Invalid plot data entry in {path} at line {line}: {source}")]
+    LogEntry {
+        path: Utf8PathBuf,
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Failed to access the plot database {path}: {source}")]
+    Database {
+        path: Utf8PathBuf,
+        #[source]
+        source: rusqlite::Error,
+    },
+    #[error("Invalid input `{input}`: {reason}")]
+    Input { input: String, reason: String },
+    #[error("Failed to get the current commit from {repo}: {source}")]
+    Git {
+        repo: Utf8PathBuf,
+        #[source]
+        source: git2::Error,
+    },
+    #[error("Failed to read the commit history of {repo}: {source}")]
+    GitHistory {
+        repo: Utf8PathBuf,
+        #[source]
+        source: git2::Error,
+    },
+    #[error("Skipped {skipped} malformed benchmark record(s) in strict mode")]
+    Strict { skipped: usize },
+    #[error("Detected {count} performance regression(s)")]
+    Regressions { count: usize },
+    #[error("Failed to render plots to {path}: {message}")]
+    Plot { path: Utf8PathBuf, message: String },
+}
+
+impl Error {
+    pub fn io(path: impl Into<Utf8PathBuf>, source: io::Error) -> Self {
+        Self::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub fn serde(path: impl Into<Utf8PathBuf>, source: serde_json::Error) -> Self {
+        Self::Serde {
+            path: path.into(),
+            source,
+        }
+    }
+
+    // Whether the error is a missing file, e.g. a history that hasn't been created yet
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Self::Io { source, .. } if source.kind() == io::ErrorKind::NotFound)
+    }
+}
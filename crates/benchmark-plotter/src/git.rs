@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use camino::Utf8Path;
+use chrono::{DateTime, FixedOffset, TimeZone};
+use git2::Repository;
+
+// The commit being benchmarked, read from the target repository at runtime rather than taken
+// from the plotter's own build, since the plotter is usually compiled from a different checkout
+#[derive(Debug, Clone)]
+pub struct CommitInfo {
+    pub sha: String,
+    // `None` when the commit was given explicitly and no repository could be read
+    pub date: Option<DateTime<FixedOffset>>,
+}
+
+impl CommitInfo {
+    // Criterion bench IDs and file names use the 7 character short SHA
+    pub fn short_sha(&self) -> &str {
+        &self.sha[..self.sha.len().min(7)]
+    }
+}
+
+// Resolves `rev` (e.g. `HEAD`, a branch or a SHA) in the repository containing `repo`.
+// If the repository can't be opened but an explicit commit was given, it's used as the SHA as-is
+pub fn resolve_commit(repo: &Utf8Path, rev: Option<&str>) -> Result<CommitInfo, git2::Error> {
+    let repository = match (Repository::discover(repo), rev) {
+        (Ok(repository), _) => repository,
+        (Err(_), Some(sha)) => {
+            return Ok(CommitInfo {
+                sha: sha.to_owned(),
+                date: None,
+            })
+        }
+        (Err(e), None) => return Err(e),
+    };
+    let commit = repository
+        .revparse_single(rev.unwrap_or("HEAD"))?
+        .peel_to_commit()?;
+
+    let time = commit.time();
+    let date = FixedOffset::east_opt(time.offset_minutes() * 60)
+        .and_then(|offset| offset.timestamp_opt(time.seconds(), 0).single());
+
+    Ok(CommitInfo {
+        sha: commit.id().to_string(),
+        date,
+    })
+}
+
+// Looks up the subject line of each commit in the repository containing `repo`. Commits that
+// can't be found, e.g. when the repository isn't available, are left out
+pub fn commit_subjects<'a>(
+    repo: &Utf8Path,
+    commits: impl IntoIterator<Item = &'a str>,
+) -> HashMap<String, String> {
+    let Ok(repository) = Repository::discover(repo) else {
+        return HashMap::new();
+    };
+    commits
+        .into_iter()
+        .filter_map(|sha| {
+            let commit = repository
+                .revparse_single(sha)
+                .ok()?
+                .peel_to_commit()
+                .ok()?;
+            Some((sha.to_owned(), commit.summary()?.to_owned()))
+        })
+        .collect()
+}
+
+// Maps the short SHA of each commit in the first-parent history of `HEAD` to its position,
+// oldest first
+pub fn first_parent_order(repo: &Utf8Path) -> Result<HashMap<String, usize>, git2::Error> {
+    let repository = Repository::discover(repo)?;
+    let mut revwalk = repository.revwalk()?;
+    revwalk.push_head()?;
+    revwalk.simplify_first_parent()?;
+    let mut commits = revwalk.collect::<Result<Vec<_>, _>>()?;
+    commits.reverse();
+    Ok(commits
+        .iter()
+        .enumerate()
+        .map(|(i, oid)| (oid.to_string()[..7].to_owned(), i))
+        .collect())
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+
+use camino::Utf8Path;
+use chrono::{DateTime, Utc};
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::archive::Manifest;
+use crate::error::Error;
+use crate::plot::Plots;
+
+// Version of the `plot-data.json` format written by this plotter. Any change to the serialized
+// types that older files can't be read as must bump it and add a step to `migrate`
+pub const SCHEMA_VERSION: u64 = 2;
+
+pub const GENERATOR: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+
+// The persistent plot data, wrapped with the information needed to read it back safely
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct History {
+    // Format version of the file, see `SCHEMA_VERSION`
+    pub version: u64,
+    // Name and version of the plotter that last wrote the file
+    pub generator: String,
+    #[serde(default)]
+    pub metadata: Metadata,
+    pub plots: Plots,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Metadata {
+    // When the history was first written, unknown for files migrated from the unversioned format
+    #[serde(default)]
+    pub created: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub updated: Option<DateTime<Utc>>,
+    // Per-run archives the history was rebuilt from, by file name
+    #[serde(default)]
+    pub archives: BTreeMap<String, ArchiveRecord>,
+}
+
+// What a rebuild read from an archive
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ArchiveRecord {
+    pub labels: Option<String>,
+    pub cargo_lock: bool,
+    // The points made from the archive, as `<group> [<params>] [@ <commit>]`
+    pub points: Vec<String>,
+    // Only in archives written by the plotter itself
+    #[serde(default)]
+    pub manifest: Option<Manifest>,
+}
+
+impl History {
+    pub fn new(plots: Plots) -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            generator: GENERATOR.to_owned(),
+            metadata: Metadata {
+                created: Some(Utc::now()),
+                ..Metadata::default()
+            },
+            plots,
+        }
+    }
+}
+
+// The JSON Schema of the current `plot-data.json` format
+pub fn schema() -> RootSchema {
+    schema_for!(History)
+}
+
+// Reads the history, migrating files written in an older format
+pub fn read_history(path: &Utf8Path) -> Result<History, Error> {
+    let s = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    let history_error = |source| Error::History {
+        path: path.to_owned(),
+        source,
+    };
+    let value = serde_json::from_str(&s).map_err(history_error)?;
+    let value = migrate(path, value)?;
+    serde_json::from_value(value).map_err(history_error)
+}
+
+// Writes the history in the current format, stamped with this plotter's version
+pub fn write_history(path: &Utf8Path, history: &mut History) -> Result<(), Error> {
+    history.version = SCHEMA_VERSION;
+    history.generator = GENERATOR.to_owned();
+    history.metadata.updated = Some(Utc::now());
+    let json_data = serde_json::to_string(&history).map_err(|source| Error::History {
+        path: path.to_owned(),
+        source,
+    })?;
+
+    std::fs::write(path, json_data).map_err(|e| Error::io(path, e))
+}
+
+// Upgrades the JSON of a history file one version at a time, up to `SCHEMA_VERSION`
+fn migrate(path: &Utf8Path, mut value: Value) -> Result<Value, Error> {
+    // The unversioned format is the bare map of plots, in which `version` would be a group name
+    // holding a plot rather than a number
+    let mut version = value.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if version > SCHEMA_VERSION {
+        return Err(Error::HistoryVersion {
+            path: path.to_owned(),
+            version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    while version < SCHEMA_VERSION {
+        value = match version {
+            0 => json!({
+                "version": 1,
+                "generator": GENERATOR,
+                "metadata": {},
+                "plots": value,
+            }),
+            // Axis ranges are derived from the points when rendering instead of being stored
+            1 => {
+                if let Some(plots) = value.get_mut("plots").and_then(Value::as_object_mut) {
+                    for plot in plots.values_mut().filter_map(Value::as_object_mut) {
+                        plot.remove("x_axis");
+                        plot.remove("y_axis");
+                    }
+                }
+                value["version"] = json!(2);
+                value
+            }
+            _ => unreachable!("Missing migration from history version {version}"),
+        };
+        version += 1;
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plots() -> Value {
+        json!({
+            "Fibonacci": {
+                "lines": {
+                    "rc=100": [{ "x": "2024-01-30T19:07:04Z", "y": 10.0 }],
+                },
+                "unit": "ns",
+            },
+        })
+    }
+
+    fn read(value: Value) -> Result<History, Error> {
+        let value = migrate(Utf8Path::new("plot-data.json"), value)?;
+        Ok(serde_json::from_value(value).unwrap())
+    }
+
+    #[test]
+    fn migrates_a_bare_map_of_plots() {
+        let history = read(plots()).unwrap();
+        assert_eq!(history.version, SCHEMA_VERSION);
+        assert_eq!(history.metadata.created, None);
+        let plot = history.plots.get("Fibonacci").unwrap();
+        assert_eq!(plot.lines["rc=100"][0].y, 10.0);
+    }
+
+    #[test]
+    fn drops_stored_axis_ranges() {
+        let mut unversioned = plots();
+        unversioned["Fibonacci"]["x_axis"] = json!({ "min": 0, "max": 1 });
+        unversioned["Fibonacci"]["y_axis"] = json!({ "min": 0.0, "max": 10.0 });
+        let v1 = json!({ "version": 1, "generator": GENERATOR, "plots": unversioned.clone() });
+
+        for value in [unversioned, v1] {
+            let value = migrate(Utf8Path::new("plot-data.json"), value).unwrap();
+            assert_eq!(value["version"], SCHEMA_VERSION);
+            let plot = value["plots"]["Fibonacci"].as_object().unwrap();
+            assert!(!plot.contains_key("x_axis") && !plot.contains_key("y_axis"));
+            assert!(plot.contains_key("lines"));
+        }
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let value = json!({ "version": SCHEMA_VERSION + 1, "generator": "", "plots": plots() });
+        match read(value) {
+            Err(Error::HistoryVersion {
+                version, supported, ..
+            }) => assert_eq!((version, supported), (SCHEMA_VERSION + 1, SCHEMA_VERSION)),
+            other => panic!("expected a version error, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt::Write;
+
+// Name of the index page linking all plots, kept from the page the deploy workflow used to build
+pub const INDEX_FILE: &str = "plots.html";
+
+// A hover target over a drawn point, in SVG pixel coordinates
+#[derive(Debug)]
+pub struct Tooltip {
+    pub x: i32,
+    pub y: i32,
+    pub text: String,
+}
+
+// Escapes text for use in HTML/SVG content and attribute values
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Wraps a plot's SVG into a self-contained HTML page. Each tooltip becomes an invisible circle
+// over its point that shows the text on hover, and the plot can be zoomed with the mouse wheel,
+// panned by dragging and reset by double-clicking
+pub fn plot_page(title: &str, svg: &str, tooltips: &[Tooltip]) -> String {
+    let mut targets = String::from(r#"<g class="tooltips">"#);
+    for tip in tooltips {
+        write!(
+            targets,
+            r#"<circle cx="{}" cy="{}" r="6"><title>{}</title></circle>"#,
+            tip.x,
+            tip.y,
+            escape(&tip.text)
+        )
+        .expect("Writing to a String can't fail");
+    }
+    targets.push_str("</g>");
+
+    // plotters writes a single root `<svg>` element, so the targets go right before it closes
+    let svg = match svg.rfind("</svg>") {
+        Some(end) => format!("{}{targets}{}", &svg[..end], &svg[end..]),
+        None => svg.to_owned(),
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+#plot svg {{ width: 100%; height: auto; cursor: grab; }}
+.tooltips circle {{ fill: transparent; stroke: none; }}
+.tooltips circle:hover {{ fill: rgba(0, 0, 0, 0.25); }}
+</style>
+</head>
+<body>
+<p><a href="{INDEX_FILE}">All benchmarks</a></p>
+<div id="plot">
+{svg}
+</div>
+<script>
+{ZOOM_SCRIPT}
+</script>
+</body>
+</html>
+"#,
+        title = escape(title),
+    )
+}
+
+// A benchmark group's section in the index page
+#[derive(Debug)]
+pub struct IndexEntry {
+    pub group: String,
+    // Plot file, relative to the index page
+    pub plot_file: String,
+    // Whether the plot is an image that can be shown inline, rather than a page to link to
+    pub image: bool,
+    pub report_url: Option<String>,
+    pub lines: Vec<IndexLine>,
+}
+
+// The latest result of a line in a plot
+#[derive(Debug)]
+pub struct IndexLine {
+    pub name: String,
+    pub value: String,
+    pub commit: Option<String>,
+    // Change from the previous result in percent, positive meaning slower
+    pub change: Option<f64>,
+}
+
+// Builds the page listing all plots, with each group's latest results and their trend
+pub fn index_page(entries: &[IndexEntry]) -> String {
+    let mut toc = String::new();
+    let mut sections = String::new();
+    for entry in entries {
+        let group = escape(&entry.group);
+        let file = escape(&entry.plot_file);
+        writeln!(toc, r##"<li><a href="#{group}">{group}</a></li>"##)
+            .expect("Writing to a String can't fail");
+
+        let mut rows = String::new();
+        for line in &entry.lines {
+            let trend = match line.change {
+                Some(change) if change > 0.0 => {
+                    format!(r#"<td class="slower">▲ {change:+.2}%</td>"#)
+                }
+                Some(change) if change < 0.0 => {
+                    format!(r#"<td class="faster">▼ {change:+.2}%</td>"#)
+                }
+                Some(_) => "<td>0.00%</td>".to_owned(),
+                None => "<td></td>".to_owned(),
+            };
+            writeln!(
+                rows,
+                "<tr><td>{}</td><td>{}</td><td>{}</td>{trend}</tr>",
+                escape(&line.name),
+                escape(&line.value),
+                escape(line.commit.as_deref().unwrap_or_default()),
+            )
+            .expect("Writing to a String can't fail");
+        }
+
+        let mut links = format!(r#"<a href="{file}">Plot</a>"#);
+        if let Some(url) = &entry.report_url {
+            write!(
+                links,
+                r#" | <a href="{}">Criterion report</a>"#,
+                escape(url)
+            )
+            .expect("Writing to a String can't fail");
+        }
+        let plot = if entry.image {
+            format!(r#"<img src="{file}" alt="Benchmark history plot for {group}">"#)
+        } else {
+            String::new()
+        };
+        write!(
+            sections,
+            r#"<section id="{group}">
+<h2>{group}</h2>
+<p>{links}</p>
+<table>
+<tr><th>Params</th><th>Latest</th><th>Commit</th><th>Change</th></tr>
+{rows}</table>
+{plot}
+</section>
+"#
+        )
+        .expect("Writing to a String can't fail");
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Benchmark history</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+img {{ width: 100%; max-width: 1024px; }}
+table {{ border-collapse: collapse; }}
+th, td {{ padding: 0.2em 1em; text-align: left; }}
+.slower {{ color: #c00; }}
+.faster {{ color: #070; }}
+</style>
+</head>
+<body>
+<h1>Benchmark history</h1>
+<ul>
+{toc}</ul>
+{sections}</body>
+</html>
+"#
+    )
+}
+
+const ZOOM_SCRIPT: &str = r##"(() => {
+  const svg = document.querySelector("#plot svg");
+  const [x0, y0, w0, h0] = [0, 0, svg.width.baseVal.value, svg.height.baseVal.value];
+  let view = { x: x0, y: y0, w: w0, h: h0 };
+  let drag = null;
+  const apply = () => svg.setAttribute("viewBox", `${view.x} ${view.y} ${view.w} ${view.h}`);
+  const toSvg = (e) => {
+    const rect = svg.getBoundingClientRect();
+    return {
+      x: view.x + ((e.clientX - rect.left) / rect.width) * view.w,
+      y: view.y + ((e.clientY - rect.top) / rect.height) * view.h,
+    };
+  };
+  svg.addEventListener("wheel", (e) => {
+    e.preventDefault();
+    const p = toSvg(e);
+    const k = e.deltaY < 0 ? 0.8 : 1.25;
+    view = { x: p.x - (p.x - view.x) * k, y: p.y - (p.y - view.y) * k, w: view.w * k, h: view.h * k };
+    apply();
+  });
+  svg.addEventListener("mousedown", (e) => { drag = toSvg(e); });
+  window.addEventListener("mouseup", () => { drag = null; });
+  svg.addEventListener("mousemove", (e) => {
+    if (!drag) return;
+    const p = toSvg(e);
+    view.x -= p.x - drag.x;
+    view.y -= p.y - drag.y;
+    apply();
+  });
+  svg.addEventListener("dblclick", () => { view = { x: x0, y: y0, w: w0, h: h0 }; apply(); });
+  apply();
+})();"##;
@@ -1,67 +1,314 @@
 use core::fmt;
-use std::io::Read;
-use std::{fs::File, path::Path};
+use std::{fs, str::FromStr};
 
-use serde::Deserialize;
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use serde_json::de::{StrRead, StreamDeserializer};
-use serde_json::{Deserializer, Error, Value};
+use serde_json::{Deserializer, Value};
 
-#[derive(Debug, Deserialize)]
+use crate::error::Error;
+
+#[derive(Debug)]
 pub struct BenchData {
     pub id: BenchId,
-    #[serde(rename = "typical")]
     pub result: BenchResult,
+    // Commit timestamp parsed from `id.date`. The Unix epoch for IDs without a date, which only
+    // `IdPattern::compare` accepts
+    pub commit_date: DateTime<Utc>,
+}
+
+// A Criterion benchmark record as emitted by `cargo criterion --message-format=json`,
+// before the ID is validated
+#[derive(Debug, Deserialize)]
+struct RawBenchData {
+    id: String,
+    #[serde(flatten)]
+    result: BenchResult,
 }
 
 #[derive(Debug)]
 pub struct BenchId {
     pub group_name: String,
-    pub bench_name: String,
+    // Short SHA of the benchmarked commit, empty if the bench ID pattern has no `commit` capture
+    pub commit: String,
+    // RFC3339 commit date
+    pub date: String,
+    // Empty if the bench ID has no params
     pub params: String,
 }
 
-// Assumes three `String` elements in a Criterion bench ID: <group>/<name>/<params>
-// E.g. `Fibonacci-num=10/28db40f-2024-01-30T19:07:04-05:00/rc=100`
-// Errors if a different format is found
-impl<'de> Deserialize<'de> for BenchId {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        let id = s.split('/').collect::<Vec<&str>>();
-        if id.len() != 3 {
-            Err(serde::de::Error::custom("Expected 3 bench ID elements"))
-        } else {
-            let bench_name = id[1].replace('_', ":");
-            Ok(BenchId {
-                group_name: id[0].to_owned(),
-                // Criterion converts `:` to `_` in the timestamp as the former is valid JSON syntax,
-                // so we convert `_` back to `:` when deserializing
-                bench_name,
-                params: id[2].to_owned(),
-            })
+// By default a Criterion bench ID is expected to be `<group>/<short-sha>-<commit-date>[/<params>]`,
+// e.g. `Fibonacci-num=10/28db40f-2024-01-30T19:07:04-05:00/rc=100`. The params are optional and
+// may contain further `/`-separated segments, e.g. `rc=100/arity=4`
+pub const DEFAULT_ID_PATTERN: &str =
+    r"^(?P<group>[^/]+)/(?P<commit>[0-9a-fA-F]{7,40})-(?P<date>[^/]+)(?:/(?P<params>.+))?$";
+
+// Bench IDs of arbitrary Criterion benchmarks, e.g. `Fibonacci/rc=100`, which only need to be
+// told apart by group and params when comparing two runs. A `<short-sha>-<commit-date>` segment
+// after the group is still recognized, so it isn't mistaken for params
+pub const COMPARE_ID_PATTERN: &str =
+    r"^(?P<group>[^/]+)(?:/(?P<commit>[0-9a-fA-F]{7,40})-(?P<date>[^/]+))?(?:/(?P<params>.+))?$";
+
+// The grammar used to split a bench ID into its parts. Must have `group` and `date` named
+// captures, and optionally `commit` and `params`
+#[derive(Debug, Clone)]
+pub struct IdPattern {
+    regex: Regex,
+    // Whether IDs without a date are skipped, which is only relaxed for comparing runs
+    require_date: bool,
+}
+
+impl Default for IdPattern {
+    fn default() -> Self {
+        DEFAULT_ID_PATTERN
+            .parse()
+            .expect("Invalid default bench ID pattern")
+    }
+}
+
+impl IdPattern {
+    // The default pattern of `compare`, see `COMPARE_ID_PATTERN`
+    pub fn compare() -> Self {
+        Self {
+            require_date: false,
+            ..COMPARE_ID_PATTERN
+                .parse()
+                .expect("Invalid compare bench ID pattern")
+        }
+    }
+}
+
+impl FromStr for IdPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let regex = Regex::new(s).map_err(|e| e.to_string())?;
+        let names = regex.capture_names().flatten().collect::<Vec<_>>();
+        for required in ["group", "date"] {
+            if !names.contains(&required) {
+                return Err(format!("Missing named capture `{required}`"));
+            }
         }
+        Ok(Self {
+            regex,
+            require_date: true,
+        })
+    }
+}
+
+impl BenchId {
+    fn parse(id: &str, pattern: &IdPattern) -> Result<Self, String> {
+        let captures = pattern
+            .regex
+            .captures(id)
+            .ok_or_else(|| format!("Doesn't match the bench ID pattern `{}`", pattern.regex))?;
+        let capture = |name| captures.name(name).map_or("", |m| m.as_str()).to_owned();
+        Ok(BenchId {
+            group_name: capture("group"),
+            commit: capture("commit"),
+            // Criterion converts `:` to `_` in the timestamp as the former is valid JSON syntax,
+            // so we convert `_` back to `:` when deserializing
+            date: capture("date").replace('_', ":"),
+            params: capture("params"),
+        })
     }
 }
 
+// The statistics Criterion computes for a benchmark. Only `typical` is required, as it's the
+// only one that was read by earlier versions of the plotter
 #[derive(Debug, Deserialize)]
 pub struct BenchResult {
-    #[serde(rename = "estimate")]
-    pub time: f64,
+    pub typical: Estimate,
+    pub mean: Option<Estimate>,
+    pub median: Option<Estimate>,
+    pub median_abs_dev: Option<Estimate>,
+    // Only present when Criterion used linear sampling
+    pub slope: Option<Estimate>,
+    // Not plotted, but kept so the full record is available to anything reading `BenchData`
+    #[allow(dead_code)]
+    #[serde(default)]
+    pub throughput: Vec<Throughput>,
+}
+
+fn default_unit() -> String {
+    "ns".into()
+}
+
+impl BenchResult {
+    pub fn estimate(&self, statistic: Statistic) -> Option<&Estimate> {
+        match statistic {
+            Statistic::Typical => Some(&self.typical),
+            Statistic::Mean => self.mean.as_ref(),
+            Statistic::Median => self.median.as_ref(),
+            Statistic::MedianAbsDev => self.median_abs_dev.as_ref(),
+            Statistic::Slope => self.slope.as_ref(),
+        }
+    }
+}
+
+// A point estimate with its confidence interval
+#[derive(Debug, Deserialize)]
+pub struct Estimate {
+    pub estimate: f64,
+    pub lower_bound: f64,
+    pub upper_bound: f64,
+    #[serde(default = "default_unit")]
+    pub unit: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct Throughput {
+    pub per_iteration: u64,
+    // E.g. `bytes` or `elements`
+    pub unit: String,
+}
+
+// Which Criterion statistic to plot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Statistic {
+    // Criterion's best estimate: the slope if available, otherwise the mean
+    #[default]
+    Typical,
+    Mean,
+    Median,
+    MedianAbsDev,
+    Slope,
+}
+
+impl fmt::Display for Statistic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("No skipped variants");
+        write!(f, "{}", value.get_name())
+    }
+}
+
+// The results read from a benchmark JSON file, along with any records that were skipped
+#[derive(Debug)]
+pub struct BenchFile {
+    pub path: Utf8PathBuf,
+    pub data: Vec<BenchData>,
+    pub skipped: Vec<Error>,
+}
+
+// A skipped record as listed in the `--report` file
+#[derive(Debug, Serialize)]
+pub struct SkippedRecord<'a> {
+    file: &'a Utf8Path,
+    offset: Option<usize>,
+    error: String,
+    // The offending JSON value, or just the bench ID if the record was otherwise valid
+    value: Option<Value>,
 }
 
-// Deserializes the benchmark JSON file into structured data for plotting
-pub fn read_json_from_file<P: AsRef<Path>>(path: P) -> Result<Vec<BenchData>, Error> {
-    let mut file = File::open(path).unwrap();
-    let mut s = String::new();
-    file.read_to_string(&mut s).unwrap();
+impl BenchFile {
+    pub fn skipped_records(&self) -> impl Iterator<Item = SkippedRecord<'_>> {
+        self.skipped.iter().map(|error| {
+            let (offset, value) = match error {
+                Error::Json { source, .. } => (Some(source.offset), source.value.clone()),
+                Error::BenchId { offset, id, .. } => (Some(*offset), Some(id.as_str().into())),
+                Error::Timestamp { offset, input, .. } => {
+                    (Some(*offset), Some(input.as_str().into()))
+                }
+                Error::Statistic { offset, id, .. } => (Some(*offset), Some(id.as_str().into())),
+                _ => (None, None),
+            };
+            SkippedRecord {
+                file: &self.path,
+                offset,
+                error: error.to_string(),
+                value,
+            }
+        })
+    }
+}
 
+// Deserializes the benchmark JSON file into structured data for plotting.
+// Malformed records are skipped and returned in `BenchFile::skipped`, only I/O errors are fatal
+pub fn read_json_from_file(
+    path: &Utf8Path,
+    pattern: &IdPattern,
+    statistic: Statistic,
+) -> Result<BenchFile, Error> {
+    let s = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    Ok(read_json_from_str(path, &s, pattern, statistic))
+}
+
+// Reads a benchmark JSON stream that is already in memory, e.g. extracted from an archive. `path`
+// is only used to identify the records in errors
+pub fn read_json_from_str(
+    path: &Utf8Path,
+    s: &str,
+    pattern: &IdPattern,
+    statistic: Statistic,
+) -> BenchFile {
     let mut data = vec![];
-    for result in ResilientStreamDeserializer::<BenchData>::new(&s).flatten() {
-        data.push(result);
+    let mut skipped = vec![];
+    let mut stream = ResilientStreamDeserializer::<RawBenchData>::new(s);
+    while let Some(result) = stream.next() {
+        let offset = stream.record_offset();
+        if matches!(&result, Err(e) if e.is_other_message()) {
+            continue;
+        }
+        let record = result.map_err(|source| Error::Json {
+            path: path.to_owned(),
+            source,
+        });
+        match record.and_then(|raw| BenchData::from_raw(raw, pattern, statistic, path, offset)) {
+            Ok(bench) => data.push(bench),
+            Err(e) => skipped.push(e),
+        }
+    }
+    BenchFile {
+        path: path.to_owned(),
+        data,
+        skipped,
+    }
+}
+
+impl BenchData {
+    fn from_raw(
+        raw: RawBenchData,
+        pattern: &IdPattern,
+        statistic: Statistic,
+        path: &Utf8Path,
+        offset: usize,
+    ) -> Result<Self, Error> {
+        let id = BenchId::parse(&raw.id, pattern).map_err(|reason| Error::BenchId {
+            path: path.to_owned(),
+            offset,
+            id: raw.id.clone(),
+            reason,
+        })?;
+        let commit_date = if id.date.is_empty() && !pattern.require_date {
+            DateTime::default()
+        } else {
+            DateTime::parse_from_rfc3339(&id.date)
+                .map(|dt| dt.with_timezone(&Utc))
+                .map_err(|source| Error::Timestamp {
+                    path: path.to_owned(),
+                    offset,
+                    input: id.date.clone(),
+                    source,
+                })?
+        };
+        if raw.result.estimate(statistic).is_none() {
+            return Err(Error::Statistic {
+                path: path.to_owned(),
+                offset,
+                id: raw.id,
+                statistic,
+            });
+        }
+        Ok(BenchData {
+            id,
+            result: raw.result,
+            commit_date,
+        })
     }
-    Ok(data)
 }
 
 // The following code is taken from https://users.rust-lang.org/t/step-past-errors-in-serde-json-streamdeserializer/84228/10
@@ -69,8 +316,20 @@ pub fn read_json_from_file<P: AsRef<Path>>(path: P) -> Result<Vec<BenchData>, Er
 // a deserialization type error or invalid JSON. See https://github.com/serde-rs/json/issues/70 for discussion
 #[derive(Debug)]
 pub struct JsonError {
-    error: Error,
+    error: serde_json::Error,
     value: Option<Value>, // Some(_) if JSON was syntactically valid
+    pub offset: usize,    // Byte offset of the record in the original JSON
+}
+
+impl JsonError {
+    // `cargo-criterion` also emits messages other than benchmark results (e.g. `group-complete`),
+    // which are expected not to deserialize as `BenchData` and aren't errors
+    fn is_other_message(&self) -> bool {
+        self.value
+            .as_ref()
+            .and_then(|value| value.get("reason"))
+            .is_some_and(|reason| reason != "benchmark-complete")
+    }
 }
 
 impl fmt::Display for JsonError {
@@ -95,6 +354,10 @@ pub struct ResilientStreamDeserializer<'de, T> {
     json: &'de str,
     stream: StreamDeserializer<'de, StrRead<'de>, T>,
     last_ok_pos: usize,
+    // Byte offset of `json` in the original input, as it's re-sliced after each error
+    base_pos: usize,
+    // Byte offset of the last record returned, in the original input
+    record_pos: usize,
 }
 
 impl<'de, T> ResilientStreamDeserializer<'de, T>
@@ -109,8 +372,15 @@ where
             json,
             stream,
             last_ok_pos,
+            base_pos: 0,
+            record_pos: 0,
         }
     }
+
+    // Byte offset of the record last returned by `next()`
+    pub fn record_offset(&self) -> usize {
+        self.record_pos
+    }
 }
 
 impl<'de, T> Iterator for ResilientStreamDeserializer<'de, T>
@@ -120,6 +390,8 @@ where
     type Item = Result<T, JsonError>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.json[self.last_ok_pos..];
+        self.record_pos = self.base_pos + self.last_ok_pos + (rest.len() - rest.trim_start().len());
         match self.stream.next()? {
             Ok(value) => {
                 self.last_ok_pos = self.stream.byte_offset();
@@ -140,8 +412,90 @@ where
                 self.json = &self.json[next_pos..];
                 self.stream = Deserializer::from_str(self.json).into_iter();
                 self.last_ok_pos = 0;
-                Some(Err(JsonError { error, value }))
+                self.base_pos += next_pos;
+                Some(Err(JsonError {
+                    error,
+                    value,
+                    offset: self.record_pos,
+                }))
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(id: &str) -> Result<BenchId, String> {
+        BenchId::parse(id, &IdPattern::default())
+    }
+
+    #[test]
+    fn parses_ids_without_params() {
+        let id = parse("Fibonacci-num=10/28db40f-2024-01-30T19:07:04-05:00").unwrap();
+        assert_eq!(id.group_name, "Fibonacci-num=10");
+        assert_eq!(id.commit, "28db40f");
+        assert_eq!(id.date, "2024-01-30T19:07:04-05:00");
+        assert_eq!(id.params, "");
+    }
+
+    #[test]
+    fn parses_ids_with_params() {
+        let id = parse("Fibonacci-num=10/28db40f-2024-01-30T19:07:04-05:00/rc=100").unwrap();
+        assert_eq!(id.group_name, "Fibonacci-num=10");
+        assert_eq!(id.commit, "28db40f");
+        assert_eq!(id.params, "rc=100");
+    }
+
+    #[test]
+    fn keeps_nested_params_together() {
+        let id = parse("LEM/28db40f-2024-01-30T19:07:04Z/rc=100/arity=4/x").unwrap();
+        assert_eq!(id.group_name, "LEM");
+        assert_eq!(id.date, "2024-01-30T19:07:04Z");
+        assert_eq!(id.params, "rc=100/arity=4/x");
+    }
+
+    #[test]
+    fn restores_colons_in_dates() {
+        let id = parse("Fibonacci/28db40f-2024-01-30T19_07_04-05_00/rc=100").unwrap();
+        assert_eq!(id.date, "2024-01-30T19:07:04-05:00");
+        assert!(DateTime::parse_from_rfc3339(&id.date).is_ok());
+    }
+
+    #[test]
+    fn rejects_ids_not_matching_the_pattern() {
+        assert!(parse("Fibonacci").is_err());
+        assert!(parse("Fibonacci/not-a-sha-2024-01-30T19:07:04Z").is_err());
+    }
+
+    #[test]
+    fn reads_custom_patterns_without_a_commit() {
+        let pattern = r"^(?P<group>[^/]+)/(?P<date>[^/]+)/(?P<params>.+)$"
+            .parse()
+            .unwrap();
+        let id = BenchId::parse("Fibonacci/2024-01-30T19_07_04Z/rc=100", &pattern).unwrap();
+        assert_eq!(id.commit, "");
+        assert_eq!(id.date, "2024-01-30T19:07:04Z");
+        assert_eq!(id.params, "rc=100");
+    }
+
+    #[test]
+    fn compares_ids_without_a_commit_or_date() {
+        let pattern = IdPattern::compare();
+        let id = BenchId::parse("Fibonacci/rc=100/arity=4", &pattern).unwrap();
+        assert_eq!((id.commit.as_str(), id.date.as_str()), ("", ""));
+        assert_eq!(id.params, "rc=100/arity=4");
+        assert_eq!(BenchId::parse("Fibonacci", &pattern).unwrap().params, "");
+
+        let id = BenchId::parse("Fibonacci/28db40f-2024-01-30T19_07_04Z/rc=100", &pattern).unwrap();
+        assert_eq!(id.commit, "28db40f");
+        assert_eq!(id.params, "rc=100");
+    }
+
+    #[test]
+    fn requires_group_and_date_captures() {
+        assert!(r"^(?P<group>.+)$".parse::<IdPattern>().is_err());
+        assert!(r"^(?P<date>.+)$".parse::<IdPattern>().is_err());
+    }
+}
@@ -1,114 +1,686 @@
+mod archive;
+mod detect;
+mod error;
+mod git;
+mod history;
+mod html;
 mod json;
+mod markdown;
+mod params;
 mod plot;
+mod store;
+mod unit;
 
 use std::{
-    io::{self, Read, Write},
-    path::PathBuf,
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    process::ExitCode,
 };
 
-use anyhow::anyhow;
-use json::read_json_from_file;
+use archive::{read_archive, write_archive, Manifest};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{Args, Parser, Subcommand};
+use detect::{compare_runs, compare_with_history, detect, Change, ChangeKind, DetectOptions};
+use error::Error;
+use git::{commit_subjects, first_parent_order, resolve_commit};
+use history::ArchiveRecord;
+use json::{read_json_from_file, BenchFile, IdPattern, Statistic};
+use store::{open_store, Query, Store, StoreKind};
 
-use crate::plot::{generate_plots, Plots};
+use crate::plot::{generate_plots, CommitOrder, DuplicatePolicy, Plots, Record, RenderOptions};
 
-// TODO: Switch to camino
-// Gets all JSON paths in the current directory, optionally ending in a given suffix
+/// CLI to plot historical Criterion benchmark results
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// Path to the persistent plot data, which is read before and added to after each run
+    #[arg(long, global = true, default_value = "plot-data.json")]
+    history: Utf8PathBuf,
+
+    /// How the plot data is stored. Defaults to `jsonl` for `.jsonl` files, `sqlite` for `.db`
+    /// and `.sqlite` files, and `json` otherwise
+    #[arg(long, global = true, value_enum)]
+    store: Option<StoreKind>,
+
+    /// Directory to write the generated plots to
+    #[arg(long, global = true, default_value = ".")]
+    out_dir: Utf8PathBuf,
+
+    /// Prefix of the `<PREFIX>_BENCH_FILES` env var used when no `--input` is given, e.g. `LURK` or `ARECIBO`
+    #[arg(long, global = true, default_value = "LURK")]
+    env_prefix: String,
+
+    /// Path to the benchmarked Git repository, used to look up the current commit
+    #[arg(long, global = true, default_value = ".")]
+    repo: Utf8PathBuf,
+
+    /// The benchmarked commit, as a SHA or any revision in `--repo`. Defaults to `HEAD`
+    #[arg(long, global = true)]
+    commit: Option<String>,
+
+    /// Without a subcommand, ingests the default benchmark files into the existing history
+    /// (or rebuilds it if missing) and renders the plots
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Adds benchmark results to the existing history
+    Ingest(InputArgs),
+    /// Renders the plots from the existing history
+    Render(RenderOptions),
+    /// Re-creates the history from scratch, discarding any existing plot data
+    Rebuild(RebuildArgs),
+    /// Writes the benchmark files of a run to `<out-dir>/<bench>-<short-sha>.tar.gz`, with
+    /// `Cargo.lock` and a manifest describing the commit, runner and environment
+    Archive(ArchiveArgs),
+    /// Compares the newest result of each benchmark to its recent history, failing if any
+    /// regressed
+    Detect(DetectArgs),
+    /// Prints a markdown table comparing benchmark results to the history, without adding them
+    Summary(SummaryArgs),
+    /// Compares two benchmark result files, e.g. of a PR and its base branch, and prints a
+    /// markdown table of the changes
+    Compare(CompareArgs),
+    /// Prints the JSON Schema of the plot data file
+    Schema,
+}
+
+#[derive(Args, Debug, Clone, Default)]
+struct InputArgs {
+    /// Benchmark JSON files to read, as file paths, glob patterns or directories. Can be repeated.
+    /// Defaults to `<PREFIX>_BENCH_FILES`, then to the JSON files for the current commit
+    #[arg(long, short)]
+    input: Vec<String>,
+
+    #[command(flatten)]
+    read: ReadArgs,
+
+    /// What to do with results for a commit and params that are already in the history
+    #[arg(long, value_enum, default_value_t = DuplicatePolicy::default())]
+    duplicates: DuplicatePolicy,
+}
+
+// How benchmark files are read, once they've been found
+#[derive(Args, Debug, Clone, Default)]
+struct ReadArgs {
+    /// Fail without writing any output if a benchmark record can't be read, or if `compare`
+    /// finds no results to compare
+    #[arg(long)]
+    strict: bool,
+
+    /// Write the skipped benchmark records and their errors to this JSON file
+    #[arg(long)]
+    report: Option<Utf8PathBuf>,
+
+    /// Regex used to split Criterion bench IDs, with named captures `group`, `date` and
+    /// optionally `commit` and `params`. `_` in the date is read as `:`. Defaults to
+    /// `<group>/<short-sha>-<commit-date>[/<params>]`, or for `compare` to `<group>[/<params>]`
+    /// where the commit and date are optional
+    #[arg(long)]
+    id_pattern: Option<IdPattern>,
+
+    /// The Criterion statistic to plot. Records without it are skipped
+    #[arg(long, value_enum, default_value_t = Statistic::default())]
+    statistic: Statistic,
+}
+
+impl ReadArgs {
+    fn id_pattern(&self) -> IdPattern {
+        self.id_pattern.clone().unwrap_or_default()
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+struct RebuildArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Per-run `.tar.gz` archives to read the benchmark files from, as file paths, glob patterns
+    /// or directories. Can be repeated. Read in addition to the `--input` files
+    #[arg(long)]
+    archives: Vec<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct ArchiveArgs {
+    /// Benchmark JSON files of the run, e.g. `fibonacci-abc1234.json`
+    #[arg(required = true)]
+    bench_files: Vec<Utf8PathBuf>,
+
+    /// Name of the archive without the `.tar.gz` extension. Defaults to the name of the first
+    /// benchmark file, e.g. `fibonacci-abc1234`
+    #[arg(long)]
+    name: Option<String>,
+
+    /// The `Cargo.lock` the benchmarks were built with
+    #[arg(long, default_value = "Cargo.lock")]
+    cargo_lock: Utf8PathBuf,
+
+    /// Labels of the runner. Defaults to the `LABELS` env var
+    #[arg(long)]
+    labels: Option<String>,
+
+    /// Env vars to record in the manifest besides those starting with `<PREFIX>_`. Can be
+    /// repeated
+    #[arg(long)]
+    env: Vec<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct DetectArgs {
+    #[command(flatten)]
+    options: DetectOptions,
+
+    /// Write the result for every benchmark line to this JSON file
+    #[arg(long)]
+    json: Option<Utf8PathBuf>,
+
+    /// Also render the plots, marking regressions and improvements on them
+    #[arg(long)]
+    plot: bool,
+
+    #[command(flatten)]
+    render: RenderOptions,
+}
+
+#[derive(Args, Debug, Clone)]
+struct SummaryArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    options: DetectOptions,
+
+    /// Append the table to this file instead of printing it, e.g. `$GITHUB_STEP_SUMMARY`
+    #[arg(long)]
+    output: Option<Utf8PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct CompareArgs {
+    /// Benchmark JSON file of the baseline, e.g. the base branch
+    base: Utf8PathBuf,
+
+    /// Benchmark JSON file to compare with the baseline, e.g. the PR branch
+    head: Utf8PathBuf,
+
+    #[command(flatten)]
+    read: ReadArgs,
+
+    #[command(flatten)]
+    options: DetectOptions,
+
+    /// Append the table to this file instead of printing it, e.g. `$GITHUB_STEP_SUMMARY`
+    #[arg(long)]
+    output: Option<Utf8PathBuf>,
+}
+
+// Benchmark files to plot, e.g. `LURK_BENCH_FILES=fibonacci-abc1234,fibonacci-def5678`
+fn bench_files_env(env_prefix: &str) -> Option<Vec<Utf8PathBuf>> {
+    let files = std::env::var(format!("{env_prefix}_BENCH_FILES")).ok()?;
+    Some(
+        files
+            .split(',')
+            .map(|file| Utf8PathBuf::from(format!("{file}.json")))
+            .collect(),
+    )
+}
+
+// Gets all JSON paths in `dir`, optionally ending in a given suffix
 // E.g. if `suffix` is `abc1234.json` it will return "*abc1234.json"
-fn get_json_paths(suffix: Option<&str>) -> std::io::Result<Vec<std::path::PathBuf>> {
+fn get_json_paths(dir: &Utf8Path, suffix: Option<&str>) -> Result<Vec<Utf8PathBuf>, Error> {
     let suffix = suffix.unwrap_or(".json");
-    let entries = std::fs::read_dir(".")?
+    let mut entries = dir
+        .read_dir_utf8()
+        .map_err(|e| Error::io(dir, e))?
         .flatten()
-        .filter_map(|e| {
-            let ext = e.path();
-            if ext.to_str()?.ends_with(suffix) {
-                Some(ext)
-            } else {
-                None
-            }
-        })
+        .map(|e| e.into_path())
+        .filter(|path| path.is_file() && path.as_str().ends_with(suffix))
         .collect::<Vec<_>>();
+    entries.sort();
     Ok(entries)
 }
 
-// Benchmark files to plot, e.g. `LURK_BENCH_FILES=fibonacci-abc1234,fibonacci-def5678`
-fn bench_files_env() -> anyhow::Result<Vec<String>> {
-    std::env::var("LURK_BENCH_FILES")
-        .map_err(|e| anyhow!("Benchmark files env var isn't set: {e}"))
-        .and_then(|commits| {
-            let vec: anyhow::Result<Vec<String>> = commits
-                .split(',')
-                .map(|sha| {
-                    sha.parse::<String>()
-                        .map_err(|e| anyhow!("Failed to parse Git commit string: {e}"))
-                })
-                .collect();
-            vec
-        })
+// Expands each `--input` into file paths: directories yield their files ending in `suffix`,
+// anything else is treated as a glob pattern (a plain file path being a pattern that matches itself)
+fn expand_inputs(inputs: &[String], suffix: &str) -> Result<Vec<Utf8PathBuf>, Error> {
+    let mut paths = vec![];
+    for input in inputs {
+        let path = Utf8Path::new(input);
+        if path.is_dir() {
+            paths.append(&mut get_json_paths(path, Some(suffix))?);
+            continue;
+        }
+        let input_error = |reason: String| Error::Input {
+            input: input.to_owned(),
+            reason,
+        };
+        let mut matches = glob::glob(input)
+            .map_err(|e| input_error(e.to_string()))?
+            .map(|entry| {
+                let path = entry.map_err(|e| input_error(e.to_string()))?;
+                Utf8PathBuf::from_path_buf(path)
+                    .map_err(|p| input_error(format!("Non UTF-8 path {}", p.display())))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(input_error("No benchmark files match".into()));
+        }
+        paths.append(&mut matches);
+    }
+    Ok(paths)
 }
 
-// Deserializes JSON file into `Plots` type
-fn read_plots_from_file() -> Result<Plots, io::Error> {
-    let path = std::path::Path::new("plot-data.json");
+impl Cli {
+    fn store(&self) -> Box<dyn Store> {
+        open_store(&self.history, self.store)
+    }
+
+    // Resolves the benchmark files to read, in order of precedence:
+    // 1. The `--input` arguments
+    // 2. The files listed in `<PREFIX>_BENCH_FILES`
+    // 3. All JSON files in the current directory, restricted to the current commit of `--repo`
+    //    (or `--commit`) unless `all` is set
+    fn bench_files(&self, input: &InputArgs, all: bool) -> Result<Vec<Utf8PathBuf>, Error> {
+        if !input.input.is_empty() {
+            return expand_inputs(&input.input, ".json");
+        }
+        if let Some(files) = bench_files_env(&self.env_prefix) {
+            return Ok(files);
+        }
+        let suffix = if all {
+            None
+        } else {
+            let commit = resolve_commit(&self.repo, self.commit.as_deref()).map_err(|source| {
+                Error::Git {
+                    repo: self.repo.clone(),
+                    source,
+                }
+            })?;
+            match commit.date {
+                Some(date) => println!("Current commit: {} ({date})", commit.sha),
+                None => println!("Current commit: {}", commit.sha),
+            }
+            Some(format!("{}.json", commit.short_sha()))
+        };
+        let paths = get_json_paths(Utf8Path::new("."), suffix.as_deref())?;
+        // Don't mistake the plot data for a benchmark file when both are in the same directory
+        Ok(paths
+            .into_iter()
+            .filter(|path| path.file_name() != self.history.file_name())
+            .collect())
+    }
+}
 
-    let mut file = std::fs::File::open(path)?;
+// Reads the benchmark files and reports any records that had to be skipped. In `--strict` mode
+// skipped records are an error
+fn read_bench_files(bench_files: &[Utf8PathBuf], read: &ReadArgs) -> Result<Vec<BenchFile>, Error> {
+    let pattern = read.id_pattern();
+    let files = bench_files
+        .iter()
+        .map(|file| read_json_from_file(file, &pattern, read.statistic))
+        .collect::<Result<Vec<BenchFile>, _>>()?;
+    check_bench_files(&files, read)?;
+    Ok(files)
+}
 
-    let mut s = String::new();
-    file.read_to_string(&mut s)?;
+// Reports the records of each file that had to be skipped, failing in `--strict` mode
+fn check_bench_files(files: &[BenchFile], read: &ReadArgs) -> Result<(), Error> {
+    for file in files {
+        println!(
+            "Read {} record(s) from {}, skipped {}",
+            file.data.len(),
+            file.path,
+            file.skipped.len()
+        );
+        for error in &file.skipped {
+            eprintln!("  {error}");
+        }
+    }
+    if let Some(report) = &read.report {
+        write_skipped_report(report, files)?;
+    }
 
-    let plots: Plots = serde_json::from_str(&s)?;
+    let skipped = files.iter().map(|file| file.skipped.len()).sum::<usize>();
+    if read.strict && skipped > 0 {
+        return Err(Error::Strict { skipped });
+    }
+    Ok(())
+}
 
-    Ok(plots)
+// Reads the benchmark files and appends their results to the history, numbering the ingestion
+// runs from `seq`. In `--strict` mode skipped records are an error and nothing is appended
+fn add_bench_files(
+    store: &dyn Store,
+    bench_files: &[Utf8PathBuf],
+    input: &InputArgs,
+    repo: &Utf8Path,
+    seq: u64,
+) -> Result<(), Error> {
+    println!("Adding bench files to plot: {:?}", bench_files);
+    let files = read_bench_files(bench_files, &input.read)?;
+    store.append(&records(&files, input, repo, seq))
 }
 
-// Serializes `Plots` type into file
-fn write_plots_to_file(plot_data: &Plots) -> Result<(), io::Error> {
-    let path = std::path::Path::new("plot-data.json");
+// Converts the results of files that have already been read and checked into records, each file
+// as its own ingestion run numbered from `seq`
+fn records(files: &[BenchFile], input: &InputArgs, repo: &Utf8Path, seq: u64) -> Vec<Record> {
+    // Commit subjects are optional context for the plots, so a missing repository isn't an error
+    let subjects = commit_subjects(
+        repo,
+        files
+            .iter()
+            .flat_map(|file| &file.data)
+            .map(|bench| bench.id.commit.as_str())
+            .filter(|commit| !commit.is_empty()),
+    );
+    files
+        .iter()
+        .zip(seq..)
+        .flat_map(|(file, seq)| {
+            Record::from_bench_data(
+                &file.data,
+                input.read.statistic,
+                &subjects,
+                input.duplicates,
+                seq,
+            )
+        })
+        .collect()
+}
 
-    let mut file = std::fs::File::create(path)?;
+// Re-creates the history from the bench JSON in per-run archives plus the input files. Files are
+// ingested from the oldest commit date, then by path, so the result doesn't depend on the order
+// the archives are found in
+fn rebuild(cli: &Cli, args: &RebuildArgs) -> Result<(), Error> {
+    let read = &args.input.read;
+    let pattern = read.id_pattern();
+    let archives = expand_inputs(&args.archives, ".tar.gz")?
+        .iter()
+        .map(|path| read_archive(path, &pattern, read.statistic))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut files = cli
+        .bench_files(&args.input, true)?
+        .iter()
+        .map(|file| read_json_from_file(file, &pattern, read.statistic))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let json_data = serde_json::to_string(&plot_data)?;
+    let mut archive_records = BTreeMap::new();
+    for archive in archives {
+        let name = archive.path.file_name().unwrap_or(archive.path.as_str());
+        let points = archive
+            .bench_files
+            .iter()
+            .flat_map(|file| &file.data)
+            .map(|bench| {
+                let mut point = bench.id.group_name.clone();
+                if !bench.id.params.is_empty() {
+                    point = format!("{point} {}", bench.id.params);
+                }
+                if !bench.id.commit.is_empty() {
+                    point = format!("{point} @ {}", bench.id.commit);
+                }
+                point
+            })
+            .collect::<Vec<_>>();
+        println!("{}: {} point(s)", archive.path, points.len());
+        for point in &points {
+            println!("  {point}");
+        }
+        for error in &archive.skipped {
+            eprintln!("  Skipped: {error}");
+        }
+        archive_records.insert(
+            name.to_owned(),
+            ArchiveRecord {
+                labels: archive.labels,
+                cargo_lock: archive.cargo_lock,
+                points,
+                manifest: archive.manifest,
+            },
+        );
+        files.extend(archive.bench_files);
+    }
 
-    file.write_all(json_data.as_bytes())
+    check_bench_files(&files, read)?;
+    files.sort_by(|a, b| {
+        let date = |file: &BenchFile| file.data.iter().map(|bench| bench.commit_date).min();
+        (date(a), &a.path).cmp(&(date(b), &b.path))
+    });
+    let records = records(&files, &args.input, &cli.repo, 1);
+    cli.store().replace(archive_records, &records)
 }
 
-fn main() {
-    // If existing plot data is found on disk, only read and add benchmark files specified by `LURK_BENCH_FILES`
-    // Data is stored in a `HashMap` so duplicates are ignored
-    let (mut plots, bench_files) = {
-        if let Ok(plots) = read_plots_from_file() {
-            // The user should know which files they just benchmarked and want to add to the plot
-            // Otherwise defaults to all files containing the current Git commit
-            let bench_files = bench_files_env().map_or_else(
-                |_| {
-                    let mut short_sha = env!("VERGEN_GIT_SHA").to_owned();
-                    short_sha.truncate(7);
-                    get_json_paths(Some(&format!("{}.json", short_sha)))
-                        .expect("Failed to read JSON paths")
-                },
-                |files| {
-                    files
-                        .iter()
-                        .map(|file| PathBuf::from(format!("{}.json", file)))
-                        .collect()
-                },
-            );
-            (plots, bench_files)
+// Archives the benchmark files of the current commit's run in `--out-dir`
+fn archive(cli: &Cli, args: &ArchiveArgs) -> Result<(), Error> {
+    let commit = resolve_commit(&cli.repo, cli.commit.as_deref()).map_err(|source| Error::Git {
+        repo: cli.repo.clone(),
+        source,
+    })?;
+    let labels = args.labels.clone().or_else(|| std::env::var("LABELS").ok());
+    let manifest = Manifest::collect(&commit, labels, &cli.env_prefix, &args.env, &cli.repo);
+    let first = &args.bench_files[0];
+    let name = match (&args.name, first.file_stem()) {
+        (Some(name), _) => name.as_str(),
+        (None, Some(stem)) => stem,
+        (None, None) => {
+            return Err(Error::Input {
+                input: first.to_string(),
+                reason: "Can't name the archive after it, pass `--name`".into(),
+            })
         }
-        // If no plot data exists, read all `JSON` files in the current directory and save to disk
-        else {
-            let paths = get_json_paths(None).expect("Failed to read JSON paths");
-            (Plots::new(), paths)
+    };
+    std::fs::create_dir_all(&cli.out_dir).map_err(|e| Error::io(&cli.out_dir, e))?;
+    let path = cli.out_dir.join(format!("{name}.tar.gz"));
+    write_archive(&path, &args.bench_files, &args.cargo_lock, &manifest)?;
+    println!("Archive has been saved to {path}");
+    Ok(())
+}
+
+// Writes all skipped records to `path` as a JSON array, so they can be inspected or re-ingested
+fn write_skipped_report(path: &Utf8Path, files: &[BenchFile]) -> Result<(), Error> {
+    let records = files
+        .iter()
+        .flat_map(BenchFile::skipped_records)
+        .collect::<Vec<_>>();
+    let json = serde_json::to_string_pretty(&records).map_err(|e| Error::serde(path, e))?;
+    std::fs::write(path, json).map_err(|e| Error::io(path, e))?;
+    println!("Skipped record report has been saved to {path}");
+    Ok(())
+}
+
+// Prints the regressions and improvements, and optionally writes every result as JSON
+fn report_changes(changes: &[Change], json: Option<&Utf8Path>) -> Result<(), Error> {
+    for change in changes.iter().filter(|c| c.is_significant()) {
+        println!("{}", change.describe());
+    }
+    let count = |kind| changes.iter().filter(|c| c.kind == kind).count();
+    println!(
+        "Compared {} benchmark(s): {} regression(s), {} improvement(s)",
+        changes.len(),
+        count(ChangeKind::Regression),
+        count(ChangeKind::Improvement)
+    );
+    if let Some(path) = json {
+        let json = serde_json::to_string_pretty(changes).map_err(|e| Error::serde(path, e))?;
+        std::fs::write(path, json).map_err(|e| Error::io(path, e))?;
+        println!("Detection result has been saved to {path}");
+    }
+    Ok(())
+}
+
+// Compares the benchmark files to the history and prints or appends the markdown table. Without
+// any history yet, every result is reported as new
+fn write_summary(cli: &Cli, args: &SummaryArgs) -> Result<(), Error> {
+    let plots = match cli.store().read(&Query::default()) {
+        Ok(plots) => plots,
+        Err(e) if e.is_not_found() => Plots::new(),
+        Err(e) => return Err(e),
+    };
+    let files = read_bench_files(&cli.bench_files(&args.input, false)?, &args.input.read)?;
+    let data = files
+        .into_iter()
+        .flat_map(|file| file.data)
+        .collect::<Vec<_>>();
+    let changes = compare_with_history(&plots, &data, args.input.read.statistic, &args.options);
+    let table = markdown::summary_table(
+        "Benchmark results",
+        &changes,
+        &format!(
+            "Baseline: median of the last {} result(s) in the history. Changes beyond \
+             ±{}% whose confidence interval excludes the baseline are marked.",
+            args.options.window, args.options.threshold
+        ),
+    );
+    write_markdown(&table, args.output.as_deref())
+}
+
+// Compares the base and head benchmark files by group and params, and prints or appends the
+// markdown table. In `--strict` mode a comparison without any results is an error
+fn write_comparison(args: &CompareArgs) -> Result<(), Error> {
+    // The IDs of arbitrary benchmarks usually have no commit or date, which matching doesn't need
+    let read = ReadArgs {
+        id_pattern: Some(
+            args.read
+                .id_pattern
+                .clone()
+                .unwrap_or_else(IdPattern::compare),
+        ),
+        ..args.read.clone()
+    };
+    let mut files = read_bench_files(&[args.base.clone(), args.head.clone()], &read)?;
+    let head = files.pop().expect("Read two files").data;
+    let base = files.pop().expect("Read two files").data;
+    let changes = compare_runs(&base, &head, read.statistic, &args.options);
+    if read.strict && changes.is_empty() {
+        return Err(Error::Input {
+            input: args.head.to_string(),
+            reason: "No benchmark results to compare".into(),
+        });
+    }
+    for change in &changes {
+        println!("{}", change.describe());
+    }
+    let table = markdown::summary_table(
+        "Benchmark comparison",
+        &changes,
+        &format!(
+            "Baseline: {}. Changes beyond ±{}% whose confidence intervals don't overlap are \
+             marked.",
+            args.base, args.options.threshold
+        ),
+    );
+    write_markdown(&table, args.output.as_deref())
+}
+
+// Appends to `output`, as `$GITHUB_STEP_SUMMARY` expects, or prints to stdout
+fn write_markdown(table: &str, output: Option<&Utf8Path>) -> Result<(), Error> {
+    match output {
+        Some(path) => {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| Error::io(path, e))?;
+            file.write_all(table.as_bytes())
+                .map_err(|e| Error::io(path, e))?;
+            println!("Markdown table has been saved to {path}");
         }
+        None => print!("{table}"),
+    }
+    Ok(())
+}
+
+fn render(
+    cli: &Cli,
+    plots: &Plots,
+    options: &RenderOptions,
+    changes: &[Change],
+) -> Result<(), Error> {
+    let git_order = match options.commit_order {
+        CommitOrder::Git => first_parent_order(&cli.repo).map_err(|source| Error::GitHistory {
+            repo: cli.repo.clone(),
+            source,
+        })?,
+        CommitOrder::Ingestion => HashMap::new(),
     };
-    println!("Adding bench files to plot: {:?}", bench_files);
-    let mut bench_data = vec![];
-    for file in bench_files {
-        let mut data = read_json_from_file(file).expect("JSON serde error");
-        bench_data.append(&mut data);
+    let out_dir = &cli.out_dir;
+    generate_plots(plots, out_dir, options, &git_order, changes).map_err(|e| Error::Plot {
+        path: out_dir.to_owned(),
+        message: e.to_string(),
+    })
+}
+
+fn run(cli: &Cli) -> Result<(), Error> {
+    match &cli.command {
+        Some(Command::Ingest(input)) => {
+            let store = cli.store();
+            // The first run creates the history
+            let seq = match store.next_seq() {
+                Ok(seq) => seq,
+                Err(e) if e.is_not_found() => 1,
+                Err(e) => return Err(e),
+            };
+            let bench_files = cli.bench_files(input, false)?;
+            add_bench_files(store.as_ref(), &bench_files, input, &cli.repo, seq)?;
+        }
+        Some(Command::Render(options)) => {
+            let plots = cli.store().read(&Query::new(options))?;
+            render(cli, &plots, options, &[])?;
+        }
+        Some(Command::Rebuild(args)) => rebuild(cli, args)?,
+        Some(Command::Archive(args)) => archive(cli, args)?,
+        Some(Command::Detect(args)) => {
+            let plots = cli.store().read(&Query::default())?;
+            let changes = detect(&plots, &args.options);
+            report_changes(&changes, args.json.as_deref())?;
+            if args.plot {
+                let plots = plots.matching(&Query::new(&args.render));
+                render(cli, &plots, &args.render, &changes)?;
+            }
+            let regressions = changes
+                .iter()
+                .filter(|c| c.kind == ChangeKind::Regression)
+                .count();
+            if regressions > 0 {
+                return Err(Error::Regressions { count: regressions });
+            }
+        }
+        Some(Command::Summary(args)) => write_summary(cli, args)?,
+        Some(Command::Compare(args)) => write_comparison(args)?,
+        Some(Command::Schema) => {
+            let schema = serde_json::to_string_pretty(&history::schema())
+                .expect("The history schema is valid JSON");
+            println!("{schema}");
+        }
+        None => {
+            // If existing plot data is found on disk, only add the given benchmark files,
+            // otherwise read all `JSON` files to re-create it
+            let input = InputArgs::default();
+            let store = cli.store();
+            let (seq, bench_files) = match store.next_seq() {
+                Ok(seq) => (seq, cli.bench_files(&input, false)?),
+                Err(e) if e.is_not_found() => (1, cli.bench_files(&input, true)?),
+                Err(e) => return Err(e),
+            };
+            add_bench_files(store.as_ref(), &bench_files, &input, &cli.repo, seq)?;
+            let plots = store.read(&Query::default())?;
+            render(cli, &plots, &RenderOptions::default(), &[])?;
+        }
     }
-    plots.add_data(&bench_data);
+    Ok(())
+}
 
-    // Write to disk
-    write_plots_to_file(&plots).expect("Failed to write `Plots` to `plot-data.json`");
-    generate_plots(&plots).unwrap();
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,71 @@
+use std::fmt::Write;
+
+use crate::detect::{Change, ChangeKind};
+
+// Renders the changes as a markdown table for PR comments or `$GITHUB_STEP_SUMMARY`, with a
+// significance marker per row and a note on what the baseline is
+pub fn summary_table(title: &str, changes: &[Change], baseline_note: &str) -> String {
+    let mut out = format!("## {title}\n\n");
+    if changes.is_empty() {
+        out.push_str("No benchmark results to compare.\n");
+        return out;
+    }
+    out.push_str("| | Benchmark | Params | Commit | Current | Baseline | Change |\n");
+    out.push_str("|---|---|---|---|--:|--:|--:|\n");
+    for change in changes {
+        let scale = change.scale();
+        let value = |v: f64| format!("{:.3} {}", v * scale.factor, scale.unit);
+        let interval = |v: f64, lower: Option<f64>, upper: Option<f64>| match (lower, upper) {
+            (Some(lower), Some(upper)) => {
+                format!("{} [{}, {}]", value(v), value(lower), value(upper))
+            }
+            _ => value(v),
+        };
+        let current = interval(change.value, change.lower, change.upper);
+        let baseline = change
+            .baseline
+            .map(|b| interval(b, change.baseline_lower, change.baseline_upper))
+            .unwrap_or_default();
+        let mut percent = change
+            .change
+            .map(|c| format!("{c:+.2}%"))
+            .unwrap_or_default();
+        if let Some((lower, upper)) = change.change_interval() {
+            percent.push_str(&format!(" [{lower:+.2}%, {upper:+.2}%]"));
+        }
+        writeln!(
+            out,
+            "| {} | {} | {} | {} | {current} | {baseline} | {percent} |",
+            marker(change.kind),
+            cell(&change.group),
+            cell(&change.params),
+            change.commit.as_deref().unwrap_or_default(),
+        )
+        .expect("Writing to a String can't fail");
+    }
+
+    let count = |kind| changes.iter().filter(|c| c.kind == kind).count();
+    write!(
+        out,
+        "\n{} regression(s), {} improvement(s), {} new benchmark(s). {baseline_note}\n",
+        count(ChangeKind::Regression),
+        count(ChangeKind::Improvement),
+        count(ChangeKind::New),
+    )
+    .expect("Writing to a String can't fail");
+    out
+}
+
+fn marker(kind: ChangeKind) -> &'static str {
+    match kind {
+        ChangeKind::Regression => ":red_circle:",
+        ChangeKind::Improvement => ":green_circle:",
+        ChangeKind::Unchanged => ":white_circle:",
+        ChangeKind::New => ":new:",
+    }
+}
+
+// Escapes text that would break out of a table cell
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
@@ -0,0 +1,269 @@
+use std::{cmp::Ordering, collections::BTreeMap, fmt, str::FromStr};
+
+// A bench ID's params split into key/value dimensions, e.g. `rc=100,arity=4` or `rc=100/arity=4`
+// is `{arity: 4, rc: 100}`. Segments without a `=` are keyed by their position, so the params of
+// Criterion's `BenchmarkId::from_parameter(100)` are `{0: 100}`
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Params(BTreeMap<String, ParamValue>);
+
+// Values that parse as numbers are compared and sorted numerically, so `rc=200` comes before
+// `rc=1000`
+#[derive(Debug, Clone)]
+pub enum ParamValue {
+    Number(f64),
+    Text(String),
+}
+
+impl ParamValue {
+    pub fn parse(s: &str) -> Self {
+        match s.parse::<f64>() {
+            Ok(n) if n.is_finite() => Self::Number(n),
+            _ => Self::Text(s.to_owned()),
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            Self::Text(_) => None,
+        }
+    }
+}
+
+impl Ord for ParamValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => a.total_cmp(b),
+            (Self::Number(_), Self::Text(_)) => Ordering::Less,
+            (Self::Text(_), Self::Number(_)) => Ordering::Greater,
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for ParamValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ParamValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ParamValue {}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{n}"),
+            Self::Text(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+impl Params {
+    pub fn parse(params: &str) -> Self {
+        Self(
+            params
+                .split([',', '/'])
+                .filter(|segment| !segment.is_empty())
+                .enumerate()
+                .map(|(i, segment)| match segment.split_once('=') {
+                    Some((key, value)) => (key.to_owned(), ParamValue::parse(value)),
+                    None => (i.to_string(), ParamValue::parse(segment)),
+                })
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ParamValue> {
+        self.0.get(key)
+    }
+
+    // The dimensions with the given keys only
+    pub fn only(&self, keys: &[&str]) -> Self {
+        Self(
+            self.0
+                .iter()
+                .filter(|(key, _)| keys.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        )
+    }
+
+    // The dimensions of both, taking `other`'s value for keys they have in common
+    pub fn union(&self, other: &Params) -> Self {
+        let mut params = self.clone();
+        params.0.extend(
+            other
+                .0
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+        params
+    }
+
+    pub fn without(&self, key: &str) -> Self {
+        let mut params = self.clone();
+        params.0.remove(key);
+        params
+    }
+
+    // Whether the params match one of the filters for each key that has any
+    pub fn matches(&self, filters: &[ParamFilter]) -> bool {
+        filters.iter().all(|filter| {
+            filters
+                .iter()
+                .filter(|f| f.key == filter.key)
+                .any(|f| self.get(&f.key) == Some(&f.value))
+        })
+    }
+}
+
+// Splits a group name into its base and the dimensions after it, e.g. `Fibonacci-num=10` is
+// `Fibonacci` and `{num: 10}`. Groups without a `<KEY>=<VALUE>` suffix have no dimensions
+pub fn split_group(group: &str) -> (&str, Params) {
+    let base_end = group
+        .find('=')
+        .and_then(|eq| group[..eq].rfind('-'))
+        .filter(|&dash| dash > 0);
+    match base_end {
+        Some(dash) => (&group[..dash], Params::parse(&group[dash + 1..])),
+        None => (group, Params::default()),
+    }
+}
+
+// Written back as `key=value` pairs sorted by key, with positional values bare
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            if key.chars().all(|c| c.is_ascii_digit()) {
+                write!(f, "{value}")?;
+            } else {
+                write!(f, "{key}={value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// A `<KEY>=<VALUE>` condition on a param
+#[derive(Debug, Clone)]
+pub struct ParamFilter {
+    pub key: String,
+    pub value: ParamValue,
+}
+
+impl FromStr for ParamFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid filter `{s}`, expected `<KEY>=<VALUE>`"))?;
+        Ok(Self {
+            key: key.to_owned(),
+            value: ParamValue::parse(value),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters(filters: &[&str]) -> Vec<ParamFilter> {
+        filters.iter().map(|f| f.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_keyed_and_positional_segments() {
+        let params = Params::parse("rc=100/arity=4,text");
+        assert_eq!(params.get("rc"), Some(&ParamValue::Number(100.0)));
+        assert_eq!(params.get("arity"), Some(&ParamValue::Number(4.0)));
+        // Positions count every segment, keyed or not
+        assert_eq!(params.get("2"), Some(&ParamValue::Text("text".into())));
+        assert_eq!(Params::parse("100"), Params::parse("0=100"));
+        assert_eq!(Params::parse(""), Params::default());
+        assert_eq!(
+            Params::parse("rc=100,,arity=4"),
+            Params::parse("rc=100/arity=4")
+        );
+    }
+
+    #[test]
+    fn orders_numbers_before_text() {
+        let cases = [
+            ("rc=200", "rc=1000"),
+            ("rc=9.5", "rc=10"),
+            ("rc=1000", "rc=abc"),
+            ("rc=abc", "rc=abd"),
+            ("arity=8,rc=100", "arity=8,rc=200"),
+        ];
+        for (smaller, larger) in cases {
+            assert!(
+                Params::parse(smaller) < Params::parse(larger),
+                "{smaller} < {larger}"
+            );
+        }
+        assert_eq!(Params::parse("rc=1e3"), Params::parse("rc=1000"));
+    }
+
+    #[test]
+    fn displays_params_sorted_by_key() {
+        let cases = [
+            ("rc=100/arity=4", "arity=4,rc=100"),
+            ("100", "100"),
+            ("x,rc=1e3", "x,rc=1000"),
+            ("", ""),
+        ];
+        for (input, expected) in cases {
+            let params = Params::parse(input);
+            assert_eq!(params.to_string(), expected);
+            assert_eq!(Params::parse(&params.to_string()), params, "{input}");
+        }
+    }
+
+    #[test]
+    fn splits_group_dimensions() {
+        let cases = [
+            ("Fibonacci-num=10", "Fibonacci", "num=10"),
+            ("Fibonacci-num=10,rc=100", "Fibonacci", "num=10,rc=100"),
+            ("Multi-part-name-num=10", "Multi-part-name", "num=10"),
+            ("Fibonacci", "Fibonacci", ""),
+            ("Fibonacci-10", "Fibonacci-10", ""),
+            ("-num=10", "-num=10", ""),
+        ];
+        for (group, base, dimensions) in cases {
+            let (split_base, split_dimensions) = split_group(group);
+            assert_eq!(split_base, base, "{group}");
+            assert_eq!(split_dimensions.to_string(), dimensions, "{group}");
+        }
+    }
+
+    #[test]
+    fn filters_any_value_of_a_key_and_every_key() {
+        let params = Params::parse("rc=100,arity=4");
+        let cases = [
+            (vec![], true),
+            (filters(&["rc=100"]), true),
+            (filters(&["rc=1e2"]), true),
+            (filters(&["rc=200"]), false),
+            (filters(&["rc=200", "rc=100"]), true),
+            (filters(&["rc=100", "arity=4"]), true),
+            (filters(&["rc=100", "arity=8"]), false),
+            (filters(&["rc=100", "arity=8", "arity=4"]), true),
+            (filters(&["missing=1"]), false),
+        ];
+        for (filters, matches) in cases {
+            assert_eq!(params.matches(&filters), matches, "{filters:?}");
+        }
+        assert!("rc".parse::<ParamFilter>().is_err());
+    }
+}
@@ -1,98 +1,1143 @@
-use plotters::prelude::*;
+use plotters::{
+    coord::{
+        ranged1d::{AsRangedCoord, ValueFormatter},
+        Shift,
+    },
+    prelude::*,
+    style::text_anchor::{HPos, Pos, VPos},
+};
 
-use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
+use camino::Utf8Path;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use clap::{Args, ValueEnum};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use std::{collections::HashMap, error::Error};
-
-use crate::json::BenchData;
-
-// TODO: Figure out how to include the commit hash as a label on the point or X-axis
-pub fn generate_plots(data: &Plots) -> Result<(), Box<dyn Error>> {
-    for plot in data.0.iter() {
-        let out_file_name = format!("./{}.png", plot.0);
-        let root = BitMapBackend::new(&out_file_name, (1024, 768)).into_drawing_area();
-        root.fill(&WHITE)?;
-
-        let mut chart = ChartBuilder::on(&root)
-            .margin(10)
-            .caption(plot.0, ("sans-serif", 40))
-            .set_label_area_size(LabelAreaPosition::Left, 60)
-            .set_label_area_size(LabelAreaPosition::Bottom, 40)
-            .build_cartesian_2d(
-                // Add one day buffer before and after
-                plot.1
-                    .x_axis
-                    .min
-                    .checked_sub_signed(Duration::days(1))
-                    .expect("DateTime underflow")
-                    ..plot
-                        .1
-                        .x_axis
-                        .max
-                        .checked_add_signed(Duration::days(1))
-                        .expect("DateTime overflow"),
-                // Add 0.2 ns buffer before and after (not rigorous, based on a priori knowledge of Y axis units & values)
-                plot.1.y_axis.min - 0.2f64..plot.1.y_axis.max + 0.2f64,
-            )?;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+};
 
-        chart
-            .configure_mesh()
-            .disable_x_mesh()
-            .disable_y_mesh()
-            .x_labels(10)
-            .max_light_lines(4)
-            .x_desc("Commit Date")
-            .y_desc("Time (ns)")
-            .draw()?;
+use crate::detect::{Change, ChangeKind};
+use crate::html::{self, IndexEntry, IndexLine, Tooltip};
+use crate::json::{BenchData, Estimate, Statistic};
+use crate::params::{split_group, ParamFilter, ParamValue, Params};
+use crate::store::Query;
+use crate::unit::{self, Scale};
 
-        // Draws the lines of benchmark data points, one line/color per set of bench ID params e.g. `rc=100`
-        for (i, line) in plot.1.lines.iter().enumerate() {
-            // Draw lines between each point
-            chart
-                .draw_series(LineSeries::new(
-                    line.1.iter().map(|p| (p.x, p.y)),
-                    Palette99::pick(i),
-                ))?
-                .label(line.0)
-                // TODO: Move the legend out of the plot area
-                .legend(move |(x, y)| {
-                    Rectangle::new(
-                        [(x - 5, y - 5), (x + 5, y + 5)],
-                        Palette99::pick(i).filled(),
-                    )
-                });
-
-            // Draw dots on each point
-            chart.draw_series(
-                line.1
+// Fraction of the Y range added as padding above and below the data
+const Y_PADDING: f64 = 0.05;
+// Factor the range of a logarithmic X axis is widened by on each side
+const LOG_X_PADDING: f64 = 1.2;
+
+// Options for how the plots are drawn
+#[derive(Args, Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// Label each commit's points with its short SHA, and PR number if known
+    #[arg(long)]
+    pub annotate: bool,
+
+    /// What the X axis shows
+    #[arg(long, value_enum, default_value_t = XAxis::default())]
+    pub x_axis: XAxis,
+
+    /// How commits are ordered with `--x-axis commit`
+    #[arg(long, value_enum, default_value_t = CommitOrder::default())]
+    pub commit_order: CommitOrder,
+
+    /// The file format of the plots
+    #[arg(long, value_enum, default_value_t = OutputFormat::default())]
+    pub format: OutputFormat,
+
+    /// Base URL of the Criterion HTML reports to link from the index page, e.g.
+    /// `../criterion/reports`
+    #[arg(long)]
+    pub criterion_url: Option<String>,
+
+    /// Only plot results of commits from this date on, as `YYYY-MM-DD` or RFC 3339
+    #[arg(long, value_parser = parse_since)]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Only plot results of the last N commits of each plot, in the `--commit-order`
+    #[arg(long)]
+    pub last: Option<usize>,
+
+    /// Use a logarithmic Y axis
+    #[arg(long, conflicts_with = "y_from_zero")]
+    pub log_y: bool,
+
+    /// Start the Y axis at zero instead of just below the lowest value
+    #[arg(long)]
+    pub y_from_zero: bool,
+
+    /// Color of a line as `<LINE>=<#RRGGBB>`, e.g. `rc=100=#1f77b4`. Can be repeated. Other
+    /// lines get a color derived from their name, so it's the same in every plot and run
+    #[arg(long, value_parser = parse_line_color)]
+    pub line_color: Vec<LineColor>,
+
+    /// Lines to draw and list first, in this order, e.g. `rc=100`. Can be repeated. Other lines
+    /// follow sorted by name
+    #[arg(long)]
+    pub line_order: Vec<String>,
+
+    /// Where to draw the legend
+    #[arg(long, value_enum, default_value_t = LegendPosition::default())]
+    pub legend: LegendPosition,
+
+    /// How the lines of a plot are laid out
+    #[arg(long, value_enum, default_value_t = Layout::default())]
+    pub layout: Layout,
+
+    /// Size of each plot as `<WIDTH>x<HEIGHT>` pixels at 96 DPI
+    #[arg(long, default_value_t = FigureSize::default())]
+    pub size: FigureSize,
+
+    /// Resolution of PNG plots in dots per inch. The figure, fonts and markers are scaled from
+    /// 96 DPI, so e.g. 192 gives twice the pixels with the same layout. Defaults to 96
+    #[arg(long)]
+    pub dpi: Option<u32>,
+
+    /// Only plot this group, by name, e.g. `Fibonacci-num=10`, or by base name, e.g.
+    /// `Fibonacci`. Can be repeated
+    #[arg(long)]
+    pub group: Vec<String>,
+
+    /// Only plot lines whose params match `<KEY>=<VALUE>`, e.g. `rc=100`. Can be repeated, and
+    /// lines must match one of the values given for each key
+    #[arg(long)]
+    pub filter: Vec<ParamFilter>,
+
+    /// Merge the lines of each plot that have the same values for these param keys, averaging
+    /// the results of each commit. Can be repeated
+    #[arg(long)]
+    pub group_by: Vec<String>,
+
+    /// Draw a grid with one chart per value of this param key, e.g. `arity`
+    #[arg(long)]
+    pub facet: Option<String>,
+
+    /// Put the values of this numeric param key on the X axis, with one line per commit, e.g.
+    /// `rc` to show how the time scales with it. Only the newest commit is drawn unless
+    /// `--at-commit` or `--last` is given
+    #[arg(long)]
+    pub pivot: Option<String>,
+
+    /// Plot the time against this numeric input on log-log axes, with one line per commit, e.g.
+    /// `num` for groups like `Fibonacci-num=10`. Groups that only differ in it are merged into
+    /// one plot named after their base, e.g. `Fibonacci`. Like `--pivot`, but the input may also
+    /// be in the group name
+    #[arg(long, conflicts_with = "pivot")]
+    pub scaling: Option<String>,
+
+    /// Use a logarithmic X axis with `--pivot`
+    #[arg(long)]
+    pub log_x: bool,
+
+    /// Commit to draw a line for with `--pivot` or `--scaling`, as a short or full SHA. Can be
+    /// repeated
+    #[arg(long)]
+    pub at_commit: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LineColor {
+    line: String,
+    color: RGBColor,
+}
+
+// Parses `<LINE>=<#RRGGBB>`, splitting at the last `=` since line names contain them
+fn parse_line_color(s: &str) -> Result<LineColor, String> {
+    let (line, hex) = s.rsplit_once('=').ok_or("Expected `<LINE>=<#RRGGBB>`")?;
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .ok_or(format!("Invalid color `{hex}`, expected `#RRGGBB`"))
+    };
+    if hex.len() != 6 {
+        return Err(format!("Invalid color `{hex}`, expected `#RRGGBB`"));
+    }
+    Ok(LineColor {
+        line: line.to_owned(),
+        color: RGBColor(channel(0)?, channel(2)?, channel(4)?),
+    })
+}
+
+fn parse_since(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|e| e.to_string())?;
+    Ok(DateTime::from_utc(date.and_hms(0, 0, 0), Utc))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Svg,
+    /// A self-contained HTML page per plot, with tooltips on each point and zoom
+    Html,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Svg => "svg",
+            OutputFormat::Html => "html",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LegendPosition {
+    /// In a panel right of the chart, so it never covers the data
+    #[default]
+    Right,
+    /// In the top right corner of the chart
+    Inside,
+    /// No legend
+    None,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// All lines of a plot on one chart
+    #[default]
+    Overlay,
+    /// One chart per line, e.g. per `rc` value, in a grid with the same axis ranges
+    Grid,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum XAxis {
+    /// Commit date, so the spacing between points reflects the time between commits
+    #[default]
+    Date,
+    /// One evenly spaced, SHA-labeled tick per commit, so same-day commits don't overlap
+    Commit,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum CommitOrder {
+    /// The order commits were ingested in, then by commit date
+    #[default]
+    Ingestion,
+    /// First-parent history of `--repo`, falling back to ingestion order for other commits
+    Git,
+}
+
+// Size of a figure in pixels at `DEFAULT_DPI`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FigureSize {
+    width: u32,
+    height: u32,
+}
+
+impl FigureSize {
+    fn scaled(self, factor: f64) -> (u32, u32) {
+        let scale = |v: u32| (f64::from(v) * factor).round() as u32;
+        (scale(self.width), scale(self.height))
+    }
+}
+
+impl Default for FigureSize {
+    fn default() -> Self {
+        Self {
+            width: 1024,
+            height: 768,
+        }
+    }
+}
+
+impl std::fmt::Display for FigureSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl std::str::FromStr for FigureSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid size `{s}`, expected `<WIDTH>x<HEIGHT>`");
+        let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+        let dimension = |v: &str| v.parse::<u32>().ok().filter(|v| *v > 0).ok_or_else(invalid);
+        Ok(Self {
+            width: dimension(width)?,
+            height: dimension(height)?,
+        })
+    }
+}
+
+// The resolution `--size` is given at, and that SVG and HTML plots are drawn at
+const DEFAULT_DPI: u32 = 96;
+
+// The most SHA tick labels drawn on a commit X axis before they start to overlap
+const MAX_COMMIT_LABELS: usize = 60;
+
+// `git_order` maps short SHAs to their position in the first-parent history, and is only used
+// with `CommitOrder::Git`
+pub fn generate_plots(
+    data: &Plots,
+    out_dir: &Utf8Path,
+    options: &RenderOptions,
+    git_order: &HashMap<String, usize>,
+    changes: &[Change],
+) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(out_dir)?;
+    // Scaling plots are pivot plots of the merged groups, drawn on log-log axes
+    let (data, options) = match &options.scaling {
+        Some(key) => (
+            Cow::Owned(data.scaling(key)),
+            Cow::Owned(RenderOptions {
+                pivot: Some(key.clone()),
+                log_x: true,
+                log_y: true,
+                y_from_zero: false,
+                ..options.clone()
+            }),
+        ),
+        None => (Cow::Borrowed(data), Cow::Borrowed(options)),
+    };
+    let options = options.as_ref();
+    let data = &data.select(options, git_order);
+    let mut skipped = HashSet::new();
+    for (name, plot) in data.0.iter() {
+        let out_file_name = out_dir.join(format!("{name}.{}", options.format.extension()));
+        let dpi = match options.format {
+            OutputFormat::Png => options.dpi.unwrap_or(DEFAULT_DPI),
+            OutputFormat::Svg | OutputFormat::Html => DEFAULT_DPI,
+        };
+        let spec = PlotSpec::new(name, plot, options, git_order, changes, dpi);
+        if spec.lines.is_empty() {
+            // E.g. none of its lines has the `--pivot` param
+            println!("Skipped {name}, which has no lines to draw");
+            skipped.insert(name);
+            continue;
+        }
+        let size = options.size.scaled(spec.dpi_scale);
+        match options.format {
+            OutputFormat::Png => {
+                let root = BitMapBackend::new(&out_file_name, size).into_drawing_area();
+                draw_plot(&root, &spec)?;
+                // To avoid the IO failure being ignored silently, we manually call the present function
+                root.present()?;
+            }
+            OutputFormat::Svg => {
+                let root = SVGBackend::new(&out_file_name, size).into_drawing_area();
+                draw_plot(&root, &spec)?;
+                root.present()?;
+            }
+            OutputFormat::Html => {
+                let mut svg = String::new();
+                let tooltips = {
+                    let root = SVGBackend::with_string(&mut svg, size).into_drawing_area();
+                    let tooltips = draw_plot(&root, &spec)?;
+                    root.present()?;
+                    tooltips
+                };
+                std::fs::write(&out_file_name, html::plot_page(name, &svg, &tooltips))?;
+            }
+        }
+        println!("Result has been saved to {}", out_file_name);
+    }
+
+    // Written from scratch every run, so removed plots don't linger
+    let index_file = out_dir.join(html::INDEX_FILE);
+    let entries = data
+        .index_entries(options)
+        .into_iter()
+        .filter(|entry| !skipped.contains(&entry.group))
+        .collect::<Vec<_>>();
+    std::fs::write(&index_file, html::index_page(&entries))?;
+    println!("Index has been saved to {}", index_file);
+
+    Ok(())
+}
+
+// A line as drawn, with its legend label and color
+struct StyledLine<'a> {
+    // The stored line the points come from, empty for lines merged by `--group-by` or `--pivot`
+    params: &'a str,
+    dimensions: Params,
+    label: String,
+    points: Cow<'a, [Point]>,
+    // The `--pivot` param's value for each point, which is its X coordinate
+    pivot: Vec<f64>,
+    color: RGBColor,
+}
+
+impl<'a> StyledLine<'a> {
+    fn new(params: &'a str, dimensions: Params, label: String, points: Cow<'a, [Point]>) -> Self {
+        Self {
+            params,
+            dimensions,
+            label,
+            points,
+            pivot: vec![],
+            color: BLACK,
+        }
+    }
+}
+
+// Everything needed to lay out and draw a plot
+struct PlotSpec<'a> {
+    name: &'a str,
+    plot: &'a Plot,
+    options: &'a RenderOptions,
+    git_order: &'a HashMap<String, usize>,
+    changes: &'a [Change],
+    // The plot's lines in drawing order
+    lines: Vec<StyledLine<'a>>,
+    // Values are stored in the unit Criterion reported, but drawn in the most readable one
+    scale: Scale,
+    // Factor from pixels at `DEFAULT_DPI` to pixels of the output
+    dpi_scale: f64,
+}
+
+impl<'a> PlotSpec<'a> {
+    fn new(
+        name: &'a str,
+        plot: &'a Plot,
+        options: &'a RenderOptions,
+        git_order: &'a HashMap<String, usize>,
+        changes: &'a [Change],
+        dpi: u32,
+    ) -> Self {
+        let mut lines = plot
+            .lines
+            .iter()
+            .map(|(params, points)| {
+                let label = line_label(name, params).to_owned();
+                StyledLine::new(params, Params::parse(params), label, points.into())
+            })
+            .collect::<Vec<_>>();
+        if !options.group_by.is_empty() {
+            lines = group_lines(name, lines, options);
+        }
+        // Listed lines first, then the rest by their params, comparing numbers numerically
+        lines.sort_by(|a, b| {
+            let position = |line: &StyledLine| {
+                options
+                    .line_order
                     .iter()
-                    .map(|p| Circle::new((p.x, p.y), 3, Palette99::pick(i).filled())),
-            )?;
+                    .position(|o| *o == line.label)
+                    .unwrap_or(usize::MAX)
+            };
+            (position(a), &a.dimensions).cmp(&(position(b), &b.dimensions))
+        });
+        if let Some(key) = &options.pivot {
+            let commits = pivot_commits(plot, options, git_order);
+            lines = pivot_lines(lines, key, &commits, options.log_x);
+        }
+        let labels = lines
+            .iter()
+            .map(|line| line.label.as_str())
+            .collect::<Vec<_>>();
+        let colors = line_colors(&labels, &options.line_color);
+        for (line, color) in lines.iter_mut().zip(colors) {
+            line.color = color;
+        }
+
+        let y_max = lines
+            .iter()
+            .flat_map(|line| line.points.iter())
+            .map(|p| p.upper.unwrap_or(p.y))
+            .fold(f64::MIN, f64::max);
+        Self {
+            name,
+            plot,
+            options,
+            git_order,
+            changes,
+            lines,
+            scale: Scale::readable(&plot.unit, y_max),
+            dpi_scale: f64::from(dpi.max(1)) / f64::from(DEFAULT_DPI),
+        }
+    }
+
+    // Converts a length in pixels at `DEFAULT_DPI`, e.g. a font size or margin, to the output
+    fn px(&self, length: u32) -> u32 {
+        (f64::from(length) * self.dpi_scale).round() as u32
+    }
+}
+
+// Merges the lines that have the same values for the `--group-by` keys, averaging the results of
+// each commit. The `--pivot` key is kept, as the pivot needs its values
+fn group_lines<'a>(
+    name: &str,
+    lines: Vec<StyledLine<'a>>,
+    options: &RenderOptions,
+) -> Vec<StyledLine<'a>> {
+    let keys = options
+        .group_by
+        .iter()
+        .chain(&options.pivot)
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let mut groups: BTreeMap<Params, Vec<StyledLine>> = BTreeMap::new();
+    for line in lines {
+        groups
+            .entry(line.dimensions.only(&keys))
+            .or_default()
+            .push(line);
+    }
+    groups
+        .into_iter()
+        .map(|(dimensions, mut group)| {
+            if group.len() == 1 {
+                let mut line = group.pop().expect("Groups aren't empty");
+                line.label = line_label(name, &dimensions.to_string()).to_owned();
+                line.dimensions = dimensions;
+                return line;
+            }
+            let mut points: BTreeMap<String, Point> = BTreeMap::new();
+            for p in group.iter().flat_map(|line| line.points.iter()) {
+                match points.get_mut(&p.commit_key()) {
+                    Some(merged) => merged.average(p.clone()),
+                    None => {
+                        points.insert(p.commit_key(), p.clone());
+                    }
+                }
+            }
+            let mut points = points.into_values().collect::<Vec<_>>();
+            points.sort_by(Point::by_date);
+            let label = line_label(name, &dimensions.to_string()).to_owned();
+            StyledLine::new("", dimensions, label, points.into())
+        })
+        .collect()
+}
+
+// The commits to draw a line for with `--pivot`: those given with `--at-commit`, otherwise all
+// commits in the `--last` window or only the newest one
+fn pivot_commits<'a>(
+    plot: &'a Plot,
+    options: &RenderOptions,
+    git_order: &HashMap<String, usize>,
+) -> Vec<&'a Point> {
+    let mut commits = plot.commits(git_order);
+    if !options.at_commit.is_empty() {
+        // Stored SHAs are short, but a full one may be given
+        commits.retain(|p| {
+            p.commit.as_deref().is_some_and(|commit| {
+                options
+                    .at_commit
+                    .iter()
+                    .any(|at| commit.starts_with(at.as_str()) || at.starts_with(commit))
+            })
+        });
+    } else if options.last.is_none() {
+        commits.drain(..commits.len().saturating_sub(1));
+    }
+    commits
+}
+
+// Turns the lines inside out for `--pivot`: each of `commits` gets a line across the values of
+// the `key` param, for every combination of the other params. Lines without a numeric `key`, or
+// without a positive one on a `log_x` axis, are left out
+fn pivot_lines<'a>(
+    lines: Vec<StyledLine<'a>>,
+    key: &str,
+    commits: &[&Point],
+    log_x: bool,
+) -> Vec<StyledLine<'a>> {
+    let mut pivoted: BTreeMap<(usize, Params), Vec<(f64, Point)>> = BTreeMap::new();
+    for line in &lines {
+        let value = line.dimensions.get(key).and_then(ParamValue::as_f64);
+        let Some(value) = value.filter(|&value| !log_x || value > 0.0) else {
+            continue;
+        };
+        for p in line.points.iter() {
+            if let Some(i) = commits
+                .iter()
+                .position(|c| c.commit_key() == p.commit_key())
+            {
+                pivoted
+                    .entry((i, line.dimensions.without(key)))
+                    .or_default()
+                    .push((value, p.clone()));
+            }
+        }
+    }
+    pivoted
+        .into_iter()
+        .map(|((i, dimensions), mut points)| {
+            points.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let commit = commits[i]
+                .label()
+                .unwrap_or_else(|| commits[i].commit_key());
+            let label = if dimensions == Params::default() {
+                commit
+            } else {
+                format!("{commit} {dimensions}")
+            };
+            let (pivot, points): (Vec<_>, Vec<_>) = points.into_iter().unzip();
+            StyledLine {
+                pivot,
+                ..StyledLine::new("", dimensions, label, points.into())
+            }
+        })
+        .collect()
+}
+
+// Draws the title, the legend panel and the chart or grid of charts
+fn draw_plot<DB>(
+    root: &DrawingArea<DB, Shift>,
+    spec: &PlotSpec,
+) -> Result<Vec<Tooltip>, Box<dyn Error>>
+where
+    DB: DrawingBackend,
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let margin = spec.px(10);
+    let area = root
+        .margin(margin, margin, margin, margin)
+        .titled(spec.name, ("sans-serif", spec.px(40)))?;
+
+    let area = match spec.options.legend {
+        LegendPosition::Right => {
+            let (chart_area, legend_area) =
+                area.split_horizontally(area.dim_in_pixel().0 - legend_width(spec, &area));
+            draw_legend(&legend_area, spec)?;
+            chart_area
+        }
+        LegendPosition::Inside | LegendPosition::None => area,
+    };
+
+    // Panels are captioned with what their lines have in common
+    let mut panels: Vec<(Option<String>, Vec<&StyledLine>)> = vec![];
+    match (&spec.options.facet, spec.options.layout) {
+        (Some(key), _) => {
+            let mut facets: BTreeMap<Option<&ParamValue>, Vec<&StyledLine>> = BTreeMap::new();
+            for line in &spec.lines {
+                facets
+                    .entry(line.dimensions.get(key))
+                    .or_default()
+                    .push(line);
+            }
+            panels.extend(facets.into_iter().map(|(value, lines)| {
+                let caption = match value {
+                    Some(value) => format!("{key}={value}"),
+                    None => format!("No {key}"),
+                };
+                (Some(caption), lines)
+            }));
+        }
+        (None, Layout::Grid) => {
+            panels.extend(
+                spec.lines
+                    .iter()
+                    .map(|line| (Some(line.label.clone()), vec![line])),
+            );
+        }
+        (None, Layout::Overlay) => panels.push((None, spec.lines.iter().collect())),
+    }
+
+    if let [(caption, lines)] = &panels[..] {
+        return draw_panel(&area, spec, caption.as_deref(), lines);
+    }
+    // As square a grid as fits the panels, filled row by row
+    let columns = (panels.len() as f64).sqrt().ceil() as usize;
+    let rows = panels.len().div_ceil(columns);
+    let mut tooltips = vec![];
+    for (area, (caption, lines)) in area.split_evenly((rows, columns)).iter().zip(&panels) {
+        tooltips.extend(draw_panel(area, spec, caption.as_deref(), lines)?);
+    }
+    Ok(tooltips)
+}
+
+// Wide enough for the longest label, but never more than a quarter of the figure
+fn legend_width<DB: DrawingBackend>(spec: &PlotSpec, area: &DrawingArea<DB, Shift>) -> u32 {
+    let longest = spec
+        .lines
+        .iter()
+        .map(|line| line.label.chars().count())
+        .max()
+        .unwrap_or_default() as u32;
+    spec.px(40 + 8 * longest).min(area.dim_in_pixel().0 / 4)
+}
+
+// Lists each line's color and label, top to bottom in drawing order
+fn draw_legend<DB>(area: &DrawingArea<DB, Shift>, spec: &PlotSpec) -> Result<(), Box<dyn Error>>
+where
+    DB: DrawingBackend,
+    DB::ErrorType: 'static,
+{
+    let px = |length: u32| spec.px(length) as i32;
+    let font = ("sans-serif", spec.px(15))
+        .into_font()
+        .color(&BLACK)
+        .pos(Pos::new(HPos::Left, VPos::Center));
+    for (i, line) in spec.lines.iter().enumerate() {
+        let y = px(20) + i as i32 * px(24);
+        area.draw(&Rectangle::new(
+            [(px(10), y - px(5)), (px(20), y + px(5))],
+            line.color.filled(),
+        ))?;
+        area.draw(&Text::new(line.label.as_str(), (px(28), y), font.clone()))?;
+    }
+    Ok(())
+}
+
+// Draws one chart of `lines`. Every chart of a plot has the same axis ranges, so the panels of a
+// grid can be compared at a glance
+fn draw_panel<DB>(
+    area: &DrawingArea<DB, Shift>,
+    spec: &PlotSpec,
+    caption: Option<&str>,
+    lines: &[&StyledLine],
+) -> Result<Vec<Tooltip>, Box<dyn Error>>
+where
+    DB: DrawingBackend,
+    DB::ErrorType: 'static,
+{
+    let options = spec.options;
+    // The Y range covers every drawn point including its confidence interval. A log axis can only
+    // show positive values, so the others are left out and drawn clamped to its bottom
+    let (y_min, y_max) = spec
+        .lines
+        .iter()
+        .flat_map(|line| line.points.iter())
+        .flat_map(|p| [p.lower.unwrap_or(p.y), p.upper.unwrap_or(p.y)])
+        .filter(|&y| !options.log_y || y > 0.0)
+        .fold((f64::MAX, f64::MIN), |(min, max), y| {
+            (min.min(y), max.max(y))
+        });
+    let (y_min, y_max) = (y_min * spec.scale.factor, y_max * spec.scale.factor);
+
+    if options.log_y {
+        // Without any positive value, fall back to a single decade
+        let y_range = if y_min <= y_max {
+            decades(y_min, y_max)
+        } else {
+            decades(1.0, 1.0)
+        };
+        draw_axes(
+            area,
+            spec,
+            caption,
+            lines,
+            y_range.start,
+            y_range.log_scale(),
+        )
+    } else {
+        // Pad the Y axis by a percentage of the range, or of the value if there's a single one
+        let y_padding = Y_PADDING
+            * if y_max > y_min {
+                y_max - y_min
+            } else {
+                y_max.abs()
+            };
+        let y_start = if options.y_from_zero {
+            0.0
+        } else {
+            y_min - y_padding
+        };
+        let y_range = y_start..y_max + y_padding;
+        draw_axes(area, spec, caption, lines, y_start, y_range)
+    }
+}
+
+// A log axis range spanning whole powers of ten, as plotters only labels a log axis from the first
+// one in range. Spans at least one, so a single value still gets a range
+fn decades(min: f64, max: f64) -> std::ops::Range<f64> {
+    let start = min.log10().floor();
+    let end = max.log10().ceil().max(start + 1.0);
+    10f64.powf(start)..10f64.powf(end)
+}
+
+// Builds the chart with the X axis selected in the options and draws the lines on it. `y_start`
+// is the bottom of `y_range`, where the commit tick labels are drawn
+fn draw_axes<DB, Y>(
+    area: &DrawingArea<DB, Shift>,
+    spec: &PlotSpec,
+    caption: Option<&str>,
+    lines: &[&StyledLine],
+    y_start: f64,
+    y_range: Y,
+) -> Result<Vec<Tooltip>, Box<dyn Error>>
+where
+    DB: DrawingBackend,
+    DB::ErrorType: 'static,
+    Y: AsRangedCoord<Value = f64>,
+    Y::CoordDescType: Ranged<ValueType = f64> + ValueFormatter<f64>,
+{
+    let PlotSpec {
+        plot,
+        options,
+        git_order,
+        ..
+    } = *spec;
+    let y_desc = format!("Time ({})", spec.scale.unit);
+    let label_style = ("sans-serif", spec.px(12));
+    let desc_style = ("sans-serif", spec.px(15));
+
+    let mut builder = ChartBuilder::on(area);
+    builder
+        .margin(spec.px(10))
+        .set_label_area_size(LabelAreaPosition::Left, spec.px(60));
+    if let Some(caption) = caption {
+        builder.caption(caption, ("sans-serif", spec.px(20)));
+    }
+
+    // Every panel spans the X values of all lines
+    let points = spec.lines.iter().flat_map(|line| line.points.iter());
+    if let Some(key) = &options.pivot {
+        let (x_min, x_max) = spec
+            .lines
+            .iter()
+            .flat_map(|line| line.pivot.iter().copied())
+            .fold((f64::MAX, f64::MIN), |(min, max), x| {
+                (min.min(x), max.max(x))
+            });
+        builder.set_label_area_size(LabelAreaPosition::Bottom, spec.px(40));
+        // About one label per 60 pixels, so they don't overlap in narrow grid panels
+        let x_labels = (area.dim_in_pixel().0 / spec.px(60)).max(2) as usize;
+        if options.log_x {
+            // Padded by a factor rather than to whole decades, which would squeeze a few inputs
+            // into a corner, as long as a power of ten is in range for the labels
+            let padded = x_min / LOG_X_PADDING..x_max * LOG_X_PADDING;
+            let x_range = if padded.start.log10().ceil() <= padded.end.log10().floor() {
+                padded
+            } else {
+                decades(x_min, x_max)
+            };
+            let x_range = x_range.log_scale();
+            return draw_pivot_axes(&mut builder, spec, key, lines, x_range, x_labels, y_range);
+        }
+        let x_padding = Y_PADDING
+            * if x_max > x_min {
+                x_max - x_min
+            } else {
+                x_max.abs().max(1.0)
+            };
+        let x_range = x_min - x_padding..x_max + x_padding;
+        return draw_pivot_axes(&mut builder, spec, key, lines, x_range, x_labels, y_range);
+    }
+
+    match options.x_axis {
+        XAxis::Date => {
+            // Shorter dates in narrow grid panels, so they don't overlap
+            let width = area.dim_in_pixel().0;
+            let date_format = if width < spec.px(500) {
+                "%m-%d"
+            } else if width < spec.px(700) {
+                "%m-%d %H:%M"
+            } else {
+                "%Y-%m-%d %H:%M"
+            };
+            let dates = points.map(|p| p.x);
+            let x_min = dates.clone().min().expect("Plots to draw aren't empty");
+            let x_max = dates.max().expect("Plots to draw aren't empty");
+            let mut chart = builder
+                .set_label_area_size(LabelAreaPosition::Bottom, spec.px(40))
+                .build_cartesian_2d(
+                    // Add one day buffer before and after
+                    x_min
+                        .checked_sub_signed(Duration::days(1))
+                        .expect("DateTime underflow")
+                        ..x_max
+                            .checked_add_signed(Duration::days(1))
+                            .expect("DateTime overflow"),
+                    y_range,
+                )?;
             chart
-                .configure_series_labels()
-                .background_style(WHITE)
-                .border_style(BLACK)
+                .configure_mesh()
+                .disable_x_mesh()
+                .disable_y_mesh()
+                .x_labels(8)
+                .max_light_lines(4)
+                .x_label_formatter(&|date| date.format(date_format).to_string())
+                .label_style(label_style)
+                .axis_desc_style(desc_style)
+                .x_desc("Commit Date")
+                .y_desc(y_desc)
                 .draw()?;
+            draw_lines(&mut chart, spec, lines, |line, i| line.points[i].x)
         }
+        XAxis::Commit => {
+            let commits = plot.commits(git_order);
+            let index = commits
+                .iter()
+                .enumerate()
+                .map(|(i, p)| (p.commit_key(), i as i32))
+                .collect::<HashMap<_, _>>();
+            let mut chart = builder
+                .set_label_area_size(LabelAreaPosition::Bottom, spec.px(100))
+                .build_cartesian_2d(-1..commits.len() as i32, y_range)?;
+            chart
+                .configure_mesh()
+                .disable_x_mesh()
+                .disable_y_mesh()
+                .x_labels(commits.len().min(MAX_COMMIT_LABELS) + 2)
+                .max_light_lines(4)
+                // Tick labels are drawn below, rotated so long labels don't overlap
+                .x_label_formatter(&|_| String::new())
+                .label_style(label_style)
+                .axis_desc_style(desc_style)
+                .x_desc("Commit")
+                .y_desc(y_desc)
+                .draw()?;
+            let font = label_style
+                .into_font()
+                .transform(FontTransform::Rotate90)
+                .color(&BLACK)
+                .pos(Pos::new(HPos::Left, VPos::Center));
+            // Chart coordinates map to the whole figure, but text is drawn relative to the panel
+            let (x0, y0) = area.get_base_pixel();
+            let step = commits.len().div_ceil(MAX_COMMIT_LABELS);
+            for (i, p) in commits.iter().enumerate().step_by(step.max(1)) {
+                let (x, y) = chart.backend_coord(&(i as i32, y_start));
+                area.draw_text(
+                    &p.label().unwrap_or_default(),
+                    &font,
+                    (x - x0, y - y0 + spec.px(8) as i32),
+                )?;
+            }
+            draw_lines(&mut chart, spec, lines, |line, i| {
+                index[&line.points[i].commit_key()]
+            })
+        }
+    }
+}
 
-        // To avoid the IO failure being ignored silently, we manually call the present function
-        root.present().expect("Unable to write result to file");
-        println!("Result has been saved to {}", out_file_name);
+// Builds a chart with the `--pivot` param's values on the X axis and draws the lines on it
+fn draw_pivot_axes<DB, X, Y>(
+    builder: &mut ChartBuilder<DB>,
+    spec: &PlotSpec,
+    key: &str,
+    lines: &[&StyledLine],
+    x_range: X,
+    x_labels: usize,
+    y_range: Y,
+) -> Result<Vec<Tooltip>, Box<dyn Error>>
+where
+    DB: DrawingBackend,
+    DB::ErrorType: 'static,
+    X: AsRangedCoord<Value = f64>,
+    X::CoordDescType: Ranged<ValueType = f64> + ValueFormatter<f64>,
+    Y: AsRangedCoord<Value = f64>,
+    Y::CoordDescType: Ranged<ValueType = f64> + ValueFormatter<f64>,
+{
+    let mut chart = builder.build_cartesian_2d(x_range, y_range)?;
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .disable_y_mesh()
+        .x_labels(x_labels)
+        .max_light_lines(4)
+        // Inputs are usually integers, so without the `.0`
+        .x_label_formatter(&|x| x.to_string())
+        .label_style(("sans-serif", spec.px(12)))
+        .axis_desc_style(("sans-serif", spec.px(15)))
+        .x_desc(key)
+        .y_desc(format!("Time ({})", spec.scale.unit))
+        .draw()?;
+    draw_lines(&mut chart, spec, lines, |line, i| line.pivot[i])
+}
+
+// Draws the lines of benchmark data points on `chart`, where `x` gives the X coordinate of a line's
+// point by its index
+fn draw_lines<'a, DB, X, Y>(
+    chart: &mut ChartContext<'a, DB, Cartesian2d<X, Y>>,
+    spec: &PlotSpec,
+    lines: &[&StyledLine],
+    x: impl Fn(&StyledLine, usize) -> X::ValueType,
+) -> Result<Vec<Tooltip>, Box<dyn Error>>
+where
+    DB: DrawingBackend + 'a,
+    DB::ErrorType: 'static,
+    X: Ranged,
+    X::ValueType: Clone + 'static,
+    Y: Ranged<ValueType = f64>,
+{
+    let PlotSpec {
+        name,
+        options,
+        changes,
+        ref scale,
+        ..
+    } = *spec;
+    let mut tooltips = vec![];
+    // Commit labels for `--annotate`, above the highest point of each commit
+    let mut labels: BTreeMap<String, (X::ValueType, f64, String)> = BTreeMap::new();
+    let stroke = spec.px(1);
+    // Values in the display unit. A log Y axis can't show values that aren't positive, so they're
+    // drawn at its bottom instead
+    let y_floor = options.log_y.then(|| chart.y_range().start);
+    let value = |y: f64| {
+        let y = y * scale.factor;
+        y_floor.map_or(y, |floor| y.max(floor))
+    };
+    // Draws the lines of benchmark data points, one line/color per set of bench ID params e.g. `rc=100`
+    for &line in lines {
+        let color = line.color;
+        let points = line
+            .points
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (x(line, i), p))
+            .collect::<Vec<_>>();
+        for (x, p) in &points {
+            let (backend_x, backend_y) = chart.backend_coord(&(x.clone(), value(p.y)));
+            tooltips.push(Tooltip {
+                x: backend_x,
+                y: backend_y,
+                text: p.tooltip(&line.label, scale),
+            });
+            if let Some(label) = p.label() {
+                let y = value(p.upper.unwrap_or(p.y));
+                let top = labels
+                    .entry(p.commit_key())
+                    .or_insert((x.clone(), y, label));
+                if y > top.1 {
+                    top.1 = y;
+                }
+            }
+        }
+
+        // Draw a translucent confidence interval band behind the line, over the points that have one
+        let bounded = points
+            .iter()
+            .filter_map(|(x, p)| Some((x.clone(), value(p.y), value(p.lower?), value(p.upper?))))
+            .collect::<Vec<_>>();
+        if bounded.len() > 1 {
+            let upper = bounded.iter().map(|(x, _, _, upper)| (x.clone(), *upper));
+            let lower = bounded
+                .iter()
+                .rev()
+                .map(|(x, _, lower, _)| (x.clone(), *lower));
+            chart.draw_series(std::iter::once(Polygon::new(
+                upper.chain(lower).collect::<Vec<_>>(),
+                color.mix(0.2).filled(),
+            )))?;
+        }
+
+        // Draw lines between each point
+        let half = spec.px(5) as i32;
+        chart
+            .draw_series(LineSeries::new(
+                points.iter().map(|(x, p)| (x.clone(), value(p.y))),
+                color.stroke_width(stroke),
+            ))?
+            .label(line.label.as_str())
+            .legend(move |(x, y)| {
+                Rectangle::new([(x - half, y - half), (x + half, y + half)], color.filled())
+            });
+
+        // Draw dots on each point, with error bars for the confidence interval
+        chart.draw_series(
+            points
+                .iter()
+                .map(|(x, p)| Circle::new((x.clone(), value(p.y)), spec.px(3), color.filled())),
+        )?;
+        chart.draw_series(bounded.iter().map(|(x, y, lower, upper)| {
+            ErrorBar::new_vertical(
+                x.clone(),
+                *lower,
+                *y,
+                *upper,
+                color.stroke_width(stroke),
+                spec.px(6),
+            )
+        }))?;
+
+        // Mark the newest point if it was detected as a regression or improvement
+        let change = changes
+            .iter()
+            .find(|c| c.group == name && c.params == line.params && c.is_significant());
+        if let (Some(change), Some((x, p))) = (change, points.last()) {
+            let color = match change.kind {
+                ChangeKind::Regression => RED,
+                _ => GREEN,
+            };
+            chart.draw_series(std::iter::once(Cross::new(
+                (x.clone(), value(p.y)),
+                spec.px(8),
+                color.stroke_width(spec.px(3)),
+            )))?;
+        }
+    }
+    // Drawn once all series are labeled, in the chart's corner
+    if options.legend == LegendPosition::Inside {
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::UpperRight)
+            .label_font(("sans-serif", spec.px(12)))
+            .background_style(WHITE)
+            .border_style(BLACK)
+            .draw()?;
     }
 
-    Ok(())
+    // Label each commit once, so the label doesn't cover other lines
+    if options.annotate {
+        let font = ("sans-serif", spec.px(12))
+            .into_font()
+            .transform(FontTransform::Rotate270)
+            .color(&BLACK);
+        let offset = (-(spec.px(6) as i32), -(spec.px(8) as i32));
+        chart.draw_series(labels.into_values().map(|(x, y, label)| {
+            EmptyElement::at((x, y)) + Text::new(label, offset, font.clone())
+        }))?;
+    }
+
+    Ok(tooltips)
 }
 
-// Convert <short-sha>-<commit-date> to a `DateTime` object, discarding `short-sha`
-fn str_to_datetime(input: &str) -> Result<DateTime<Utc>, Box<dyn Error>> {
-    // Removes the first 8 chars (assuming UTF8) for the `short-sha` and trailing '-'
-    let datetime: &str = input.split_at(8).1;
+// Bench IDs without params have a single line, named after the plot
+fn line_label<'a>(name: &'a str, params: &'a str) -> &'a str {
+    if params.is_empty() {
+        name
+    } else {
+        params
+    }
+}
 
-    DateTime::parse_from_rfc3339(datetime).map_or_else(
-        |e| Err(format!("Failed to parse string into `DateTime`: {}", e).into()),
-        |dt| Ok(dt.with_timezone(&Utc)),
-    )
+// Picks the color of each line. Lines without a `--line-color` get a palette color from a hash of
+// their name, or the next free one if another line in the plot already has it, so a line keeps
+// its color across runs unless a colliding line is added
+fn line_colors(labels: &[&str], configured: &[LineColor]) -> Vec<RGBColor> {
+    let configured_color =
+        |label: &str| configured.iter().find(|c| c.line == label).map(|c| c.color);
+    let mut used = labels
+        .iter()
+        .filter_map(|label| configured_color(label))
+        .collect::<Vec<_>>();
+    labels
+        .iter()
+        .map(|label| {
+            if let Some(color) = configured_color(label) {
+                return color;
+            }
+            let palette = Palette99::COLORS;
+            let start = stable_hash(label) as usize % palette.len();
+            let color = (start..start + palette.len())
+                .map(|i| {
+                    let (r, g, b) = palette[i % palette.len()];
+                    RGBColor(r, g, b)
+                })
+                .find(|color| !used.contains(color))
+                .unwrap_or(RGBColor(
+                    palette[start].0,
+                    palette[start].1,
+                    palette[start].2,
+                ));
+            used.push(color);
+            color
+        })
+        .collect()
+}
+
+// FNV-1a, as the std hasher's output isn't guaranteed to be the same across Rust releases
+fn stable_hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
 }
 
 // Plots of benchmark results over time/Git history. This data structure is persistent between runs,
@@ -103,133 +1148,597 @@ fn str_to_datetime(input: &str) -> Result<DateTime<Utc>, Box<dyn Error>> {
 // information to view multiple benchmark input results on the same graph (e.g. fib-10 and fib-20),
 // since they are expected to be different. Instead, we group different benchmark parameters
 // (e.g. `rc` value) onto the same graph to compare/contrast their impact on performance.
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Plots(HashMap<String, Plot>);
+//
+// Plots and their lines are kept sorted by name, so they're drawn and saved in the same order for
+// the same data.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Plots(BTreeMap<String, Plot>);
 
 impl Plots {
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self(BTreeMap::new())
     }
 
-    // Converts a list of deserialized Criterion benchmark results into a plotting-friendly format,
-    // and adds the data to the `Plots` struct.
-    pub fn add_data(&mut self, bench_data: &Vec<BenchData>) {
-        for bench in bench_data {
-            let commit_date = str_to_datetime(&bench.id.bench_name).expect("Timestamp parse error");
-            let point = Point {
-                x: commit_date,
-                y: bench.result.time,
-            };
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Plot)> {
+        self.0.iter()
+    }
 
-            if self.0.get(&bench.id.group_name).is_none() {
-                self.0.insert(bench.id.group_name.to_owned(), Plot::new());
-            }
-            let plot = self.0.get_mut(&bench.id.group_name).unwrap();
+    pub fn get(&self, group: &str) -> Option<&Plot> {
+        self.0.get(group)
+    }
+
+    // The number of the next ingestion run, so commits can be plotted in the order they were added
+    pub fn next_seq(&self) -> u64 {
+        self.0
+            .values()
+            .flat_map(|plot| plot.lines.values().flatten())
+            .map(|p| p.seq)
+            .max()
+            .unwrap_or_default()
+            + 1
+    }
 
-            plot.x_axis.set_min_max(commit_date);
-            plot.y_axis.set_min_max(point.y);
+    // Adds stored benchmark results in the order they were recorded. Results for a commit and
+    // params that are already plotted are handled according to each record's `duplicates`
+    pub fn add_records(&mut self, records: &[Record]) {
+        for record in records {
+            let plot = self
+                .0
+                .entry(record.group.clone())
+                .or_insert_with(|| Plot::new(&record.unit));
+            // Keep all of a plot's values in the unit of its first point
+            let factor = unit::factor(&record.unit, &plot.unit);
+            let point = record.point.rescaled(factor);
 
-            if plot.lines.get(&bench.id.params).is_none() {
-                plot.lines.insert(bench.id.params.to_owned(), vec![]);
+            // A point is identified by its commit and params, so a commit that is benchmarked
+            // again or a file that is ingested twice doesn't add points by default
+            let line = plot.lines.entry(record.params.clone()).or_default();
+            let existing = line
+                .iter_mut()
+                .find(|p| p.commit_key() == point.commit_key());
+            match (existing, record.duplicates) {
+                (Some(existing), DuplicatePolicy::Replace) => existing.replace(point),
+                (Some(existing), DuplicatePolicy::Average) => existing.average(point),
+                _ => line.push(point),
             }
-            plot.lines.get_mut(&bench.id.params).unwrap().push(point);
         }
         // Sort each data point in each line for each plot
         for plot in self.0.iter_mut() {
             for line in plot.1.lines.iter_mut() {
-                line.1.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                line.1.sort_by(Point::by_date);
+            }
+        }
+    }
+
+    // The plots restricted to the lines matching `query`
+    pub fn matching(&self, query: &Query) -> Plots {
+        let mut plots = Plots::new();
+        for (name, plot) in &self.0 {
+            let lines = plot
+                .lines
+                .iter()
+                .map(|(params, points)| {
+                    let points = points
+                        .iter()
+                        .filter(|p| query.matches(name, params, p.x))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    (params.clone(), points)
+                })
+                .filter(|(_, points)| !points.is_empty())
+                .collect::<BTreeMap<_, _>>();
+            if !lines.is_empty() {
+                let unit = plot.unit.clone();
+                plots.0.insert(name.clone(), Plot { lines, unit });
             }
         }
+        plots
     }
+
+    // The plots restricted to the `--since` and `--last` window and `--filter`, leaving out plots
+    // with no points in it
+    fn select(&self, options: &RenderOptions, git_order: &HashMap<String, usize>) -> Plots {
+        Plots(
+            self.0
+                .iter()
+                .map(|(name, plot)| (name.clone(), plot.select(options, git_order)))
+                .filter(|(_, plot)| plot.lines.values().any(|line| !line.is_empty()))
+                .collect(),
+        )
+    }
+
+    // Merges the plots of groups that have the `key` dimension and the same base, e.g.
+    // `Fibonacci-num=10` and `Fibonacci-num=20` into `Fibonacci`, moving the dimensions of each
+    // group into the params of its lines. Other plots are kept as they are
+    fn scaling(&self, key: &str) -> Plots {
+        let mut plots = Plots::new();
+        for (name, plot) in &self.0 {
+            let (base, dimensions) = match split_group(name) {
+                (base, dimensions) if dimensions.get(key).is_some() => (base, dimensions),
+                _ => (name.as_str(), Params::default()),
+            };
+            let merged = plots
+                .0
+                .entry(base.to_owned())
+                .or_insert_with(|| Plot::new(&plot.unit));
+            // Merged groups may have been reported in different units
+            let factor = unit::factor(&plot.unit, &merged.unit);
+            for (params, points) in &plot.lines {
+                let params = if dimensions == Params::default() {
+                    params.clone()
+                } else {
+                    dimensions.union(&Params::parse(params)).to_string()
+                };
+                let line = merged.lines.entry(params).or_default();
+                line.extend(points.iter().map(|p| p.rescaled(factor)));
+                line.sort_by(Point::by_date);
+            }
+        }
+        plots
+    }
+
+    // The index page sections for all plots, sorted by group name
+    fn index_entries(&self, options: &RenderOptions) -> Vec<IndexEntry> {
+        self.0
+            .iter()
+            .map(|(name, plot)| IndexEntry {
+                group: name.to_owned(),
+                plot_file: format!("{name}.{}", options.format.extension()),
+                image: options.format != OutputFormat::Html,
+                report_url: options
+                    .criterion_url
+                    .as_ref()
+                    .map(|url| format!("{url}/{}/index.html", report_dir_name(name))),
+                lines: plot.latest(name, &options.line_order),
+            })
+            .collect()
+    }
+}
+
+// Criterion replaces characters that aren't safe in file names when naming report directories
+fn report_dir_name(group: &str) -> String {
+    group
+        .chars()
+        .map(|c| match c {
+            '?' | '"' | '/' | '\\' | '*' | '<' | '>' | ':' | '|' | '^' => '_',
+            _ => c,
+        })
+        .collect()
 }
 
-// The data type for a plot: contains the range of X and Y values, and the line(s) to be drawn
-#[derive(Debug, Serialize, Deserialize)]
+// The data type for a plot: contains the line(s) to be drawn. Axis ranges are derived from the
+// points when rendering
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Plot {
-    x_axis: XAxisRange,
-    y_axis: YAxisRange,
-    lines: HashMap<String, Vec<Point>>,
+    pub lines: BTreeMap<String, Vec<Point>>,
+    // Unit of the Y values as reported by Criterion for the first point
+    #[serde(default = "default_unit")]
+    pub unit: String,
+}
+
+fn default_unit() -> String {
+    "ns".into()
 }
 
 impl Plot {
-    pub fn new() -> Self {
+    // One point per distinct commit in the plot, in commit order
+    fn commits(&self, git_order: &HashMap<String, usize>) -> Vec<&Point> {
+        let mut commits: BTreeMap<String, &Point> = BTreeMap::new();
+        for p in self.lines.values().flatten() {
+            let first = commits.entry(p.commit_key()).or_insert(p);
+            if (p.seq, p.x) < (first.seq, first.x) {
+                *first = p;
+            }
+        }
+        let mut commits = commits.into_values().collect::<Vec<_>>();
+        commits.sort_by_key(|p| {
+            let git_position = p
+                .commit
+                .as_ref()
+                .and_then(|commit| git_order.get(commit.get(..7).unwrap_or(commit)));
+            (git_position.copied().unwrap_or(usize::MAX), p.seq, p.x)
+        });
+        commits
+    }
+
+    // The lines whose last point is of the plot's newest commit in ingestion order, leaving out
+    // lines that the latest run didn't benchmark
+    pub fn current_lines(&self) -> impl Iterator<Item = (&String, &Vec<Point>)> {
+        let newest = self.commits(&HashMap::new()).last().map(|p| p.commit_key());
+        self.lines
+            .iter()
+            .filter(move |(_, points)| points.last().map(Point::commit_key) == newest)
+    }
+
+    // The lines listed in `order` first, in that order, then the rest sorted by their params
+    fn ordered_lines(&self, order: &[String]) -> Vec<(&String, &Vec<Point>)> {
+        let mut lines = self.lines.iter().collect::<Vec<_>>();
+        lines.sort_by_cached_key(|(params, _)| {
+            let position = order.iter().position(|o| o == *params);
+            (position.unwrap_or(usize::MAX), Params::parse(params))
+        });
+        lines
+    }
+
+    // The latest point of each line, in line order, compared to the point before it
+    fn latest(&self, name: &str, order: &[String]) -> Vec<IndexLine> {
+        self.ordered_lines(order)
+            .into_iter()
+            .filter_map(|(params, points)| {
+                let latest = points.last()?;
+                let previous = points.len().checked_sub(2).map(|i| &points[i]);
+                let scale = Scale::readable(&self.unit, latest.y);
+                Some(IndexLine {
+                    name: line_label(name, params).to_owned(),
+                    value: format!("{:.3} {}", latest.y * scale.factor, scale.unit),
+                    commit: latest.label(),
+                    change: previous
+                        .filter(|p| p.y != 0.0)
+                        .map(|p| (latest.y - p.y) / p.y * 100.0),
+                })
+            })
+            .collect()
+    }
+
+    // The points in the `--since` and `--last` window, of the lines matching `--filter`
+    fn select(&self, options: &RenderOptions, git_order: &HashMap<String, usize>) -> Plot {
+        let last = options.last.map(|n| {
+            let commits = self.commits(git_order);
+            commits[commits.len().saturating_sub(n)..]
+                .iter()
+                .map(|p| p.commit_key())
+                .collect::<HashSet<_>>()
+        });
+        let in_window = |p: &&Point| {
+            options.since.map_or(true, |since| p.x >= since)
+                && last
+                    .as_ref()
+                    .map_or(true, |last| last.contains(&p.commit_key()))
+        };
+        let lines = self
+            .lines
+            .iter()
+            .filter(|(params, _)| Params::parse(params).matches(&options.filter))
+            .map(|(params, points)| {
+                let points = points.iter().filter(in_window).cloned().collect::<Vec<_>>();
+                (params.clone(), points)
+            })
+            .filter(|(_, points)| !points.is_empty())
+            .collect();
+        Plot {
+            lines,
+            unit: self.unit.clone(),
+        }
+    }
+
+    // Converts a benchmark estimate into a point, keeping all of a plot's values in the same unit
+    pub fn point(&self, bench: &BenchData, estimate: &Estimate) -> Point {
+        let factor = unit::factor(&estimate.unit, &self.unit);
+        Point {
+            x: bench.commit_date,
+            y: estimate.estimate * factor,
+            lower: Some(estimate.lower_bound * factor),
+            upper: Some(estimate.upper_bound * factor),
+            commit: Some(bench.id.commit.to_owned()).filter(|commit| !commit.is_empty()),
+            subject: None,
+            seq: 0,
+            runs: 1,
+        }
+    }
+
+    pub fn new(unit: &str) -> Self {
         Self {
-            x_axis: XAxisRange::default(),
-            y_axis: YAxisRange::default(),
-            lines: HashMap::new(),
+            lines: BTreeMap::new(),
+            unit: unit.to_owned(),
         }
     }
 }
 
+// A benchmark result as it's stored, before it's merged into the plot of its group
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Record {
+    pub group: String,
+    pub params: String,
+    // Unit of the point's values as reported by Criterion
+    pub unit: String,
+    // How the point is merged with an earlier result of the same commit and params
+    #[serde(default)]
+    pub duplicates: DuplicatePolicy,
+    #[serde(flatten)]
+    pub point: Point,
+}
+
+impl Record {
+    // Converts deserialized Criterion benchmark results into the records of ingestion run `seq`.
+    // Results without the given `statistic` are left out, as they are skipped when read.
+    // `subjects` maps commit SHAs to their subject line, where known
+    pub fn from_bench_data(
+        bench_data: &[BenchData],
+        statistic: Statistic,
+        subjects: &HashMap<String, String>,
+        duplicates: DuplicatePolicy,
+        seq: u64,
+    ) -> Vec<Record> {
+        bench_data
+            .iter()
+            .filter_map(|bench| {
+                let estimate = bench.result.estimate(statistic)?;
+                let point = Point {
+                    subject: subjects.get(&bench.id.commit).cloned(),
+                    seq,
+                    ..Plot::new(&estimate.unit).point(bench, estimate)
+                };
+                Some(Record {
+                    group: bench.id.group_name.clone(),
+                    params: bench.id.params.clone(),
+                    unit: estimate.unit.clone(),
+                    duplicates,
+                    point,
+                })
+            })
+            .collect()
+    }
+}
+
 // Historical benchmark result, showing the performance at a given Git commit
-#[derive(Debug, Serialize, Deserialize, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, PartialOrd)]
 pub struct Point {
     // Commit timestamp associated with benchmark
-    x: DateTime<Utc>,
-    // Benchmark time (avg.)
-    y: f64,
+    pub x: DateTime<Utc>,
+    // Benchmark time, as the statistic selected at ingestion
+    pub y: f64,
+    // Confidence interval of `y`, missing for points ingested before it was recorded
+    #[serde(default)]
+    pub lower: Option<f64>,
+    #[serde(default)]
+    pub upper: Option<f64>,
+    // Short SHA of the benchmarked commit
+    #[serde(default)]
+    pub commit: Option<String>,
+    // Subject line of the commit, if the repository was available at ingestion
+    #[serde(default)]
+    subject: Option<String>,
+    // Number of the ingestion run that added the point, 0 if it predates numbering
+    #[serde(default)]
+    seq: u64,
+    // Number of benchmark results averaged into the point
+    #[serde(default = "default_runs")]
+    runs: u32,
 }
 
-// Min. and max. X axis values for a given plot
-#[derive(Debug, Serialize, Deserialize)]
-pub struct XAxisRange {
-    #[serde(with = "ts_seconds")]
-    min: DateTime<Utc>,
-    #[serde(with = "ts_seconds")]
-    max: DateTime<Utc>,
+fn default_runs() -> u32 {
+    1
 }
 
-// Starts with flipped min/max so they can be set by `Point` values as they are encountered
-impl Default for XAxisRange {
-    fn default() -> Self {
-        Self {
-            min: Utc::now(),
-            max: chrono::DateTime::<Utc>::MIN_UTC,
-        }
-    }
+// What to do when a commit's result is ingested for params that already have one
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    /// Replace the earlier result with the new one
+    #[default]
+    Replace,
+    /// Keep both results as separate points
+    Keep,
+    /// Average all results into a single point
+    Average,
 }
 
-// Min. and max. Y axis values for a given plot
-#[derive(Debug, Serialize, Deserialize)]
-pub struct YAxisRange {
-    min: f64,
-    max: f64,
-}
+impl Point {
+    // Takes the values of `other`, keeping the ingestion run that first added the commit so its
+    // position on a commit X axis doesn't change
+    fn replace(&mut self, other: Point) {
+        *self = Point {
+            seq: self.seq,
+            ..other
+        };
+    }
 
-// Starts with flipped min/max so they can be set by `Point` values as they are encountered
-impl Default for YAxisRange {
-    fn default() -> Self {
-        Self {
-            min: f64::MAX,
-            max: f64::MIN,
+    // The point with its values multiplied by `factor`, e.g. to convert them to another unit
+    fn rescaled(&self, factor: f64) -> Point {
+        Point {
+            y: self.y * factor,
+            lower: self.lower.map(|lower| lower * factor),
+            upper: self.upper.map(|upper| upper * factor),
+            ..self.clone()
         }
     }
-}
 
-// Checks if input is < the current min and/or > current max
-// If so, sets input as the new min and/or max respectively
-trait MinMax<T: PartialOrd> {
-    fn set_min_max(&mut self, value: T);
-}
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
 
-impl MinMax<DateTime<Utc>> for XAxisRange {
-    fn set_min_max(&mut self, value: DateTime<Utc>) {
-        if value < self.min {
-            self.min = value
-        }
-        if value > self.max {
-            self.max = value
+    // Folds `other` into the running mean of the results averaged so far
+    fn average(&mut self, other: Point) {
+        let runs = f64::from(self.runs);
+        let mean = |a: f64, b: f64| (a * runs + b) / (runs + 1.0);
+        self.y = mean(self.y, other.y);
+        self.lower = self.lower.zip(other.lower).map(|(a, b)| mean(a, b));
+        self.upper = self.upper.zip(other.upper).map(|(a, b)| mean(a, b));
+        self.subject = self.subject.take().or(other.subject);
+        self.runs += 1;
+    }
+
+    // The PR number from a GitHub merge subject, e.g. `Add foo (#123)`
+    fn pr_number(&self) -> Option<&str> {
+        let subject = self.subject.as_deref()?;
+        let start = subject.rfind("(#")? + 2;
+        let number = subject[start..].strip_suffix(')')?;
+        number.chars().all(|c| c.is_ascii_digit()).then_some(number)
+    }
+
+    // Identifies the point's commit, falling back to its date for points without a SHA
+    fn commit_key(&self) -> String {
+        self.commit.clone().unwrap_or_else(|| self.x.to_rfc3339())
+    }
+
+    // The order of a line's points: by date, then by value for points of the same commit
+    fn by_date(a: &Point, b: &Point) -> Ordering {
+        a.x.cmp(&b.x).then(a.y.total_cmp(&b.y))
+    }
+
+    // Hover text for the point in HTML plots, in the plot's display unit
+    fn tooltip(&self, line: &str, scale: &Scale) -> String {
+        let mut text = match (&self.commit, &self.subject) {
+            (Some(commit), Some(subject)) => format!("{commit} {subject}\n"),
+            (Some(commit), None) => format!("{commit}\n"),
+            _ => String::new(),
+        };
+        let value = |v: f64| format!("{:.3} {}", v * scale.factor, scale.unit);
+        text.push_str(&format!(
+            "{}\n{line}: {}",
+            self.x.format("%Y-%m-%d %H:%M UTC"),
+            value(self.y)
+        ));
+        if let (Some(lower), Some(upper)) = (self.lower, self.upper) {
+            text.push_str(&format!(" [{}, {}]", value(lower), value(upper)));
         }
+        text
+    }
+
+    // Short text identifying the point's commit, e.g. `28db40f #123`
+    fn label(&self) -> Option<String> {
+        let commit = self.commit.as_deref()?;
+        Some(match self.pr_number() {
+            Some(pr) => format!("{commit} #{pr}"),
+            None => commit.to_owned(),
+        })
     }
 }
 
-impl MinMax<f64> for YAxisRange {
-    fn set_min_max(&mut self, value: f64) {
-        if value < self.min {
-            self.min = value
-        }
-        if value > self.max {
-            self.max = value
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(commit: &str, y: f64, seq: u64, duplicates: DuplicatePolicy) -> Record {
+        serde_json::from_value(serde_json::json!({
+            "group": "Fibonacci",
+            "params": "rc=100",
+            "unit": "ns",
+            "duplicates": duplicates,
+            "x": "2024-01-30T19:07:04Z",
+            "y": y,
+            "lower": y - 1.0,
+            "upper": y + 1.0,
+            "commit": commit,
+            "seq": seq,
+        }))
+        .unwrap()
+    }
+
+    fn line(plots: &Plots) -> &[Point] {
+        &plots.get("Fibonacci").unwrap().lines["rc=100"]
+    }
+
+    #[test]
+    fn replace_makes_reingesting_idempotent() {
+        let records = [record("28db40f", 10.0, 1, DuplicatePolicy::Replace)];
+        let mut plots = Plots::new();
+        plots.add_records(&records);
+        let once = line(&plots).to_vec();
+        plots.add_records(&records);
+        assert_eq!(line(&plots), once);
+
+        // A new result for the commit takes its place, in its original ingestion run
+        plots.add_records(&[record("28db40f", 12.0, 2, DuplicatePolicy::Replace)]);
+        let points = line(&plots);
+        assert_eq!(points.len(), 1);
+        assert_eq!((points[0].y, points[0].seq, points[0].runs), (12.0, 1, 1));
+    }
+
+    #[test]
+    fn keep_adds_every_result() {
+        let mut plots = Plots::new();
+        plots.add_records(&[
+            record("28db40f", 12.0, 1, DuplicatePolicy::Keep),
+            record("28db40f", 10.0, 2, DuplicatePolicy::Keep),
+        ]);
+        let points = line(&plots);
+        assert_eq!(points.len(), 2);
+        // Points of the same commit are ordered by value
+        assert_eq!((points[0].y, points[0].seq), (10.0, 2));
+        assert_eq!((points[1].y, points[1].seq), (12.0, 1));
+    }
+
+    #[test]
+    fn average_keeps_a_running_mean() {
+        let mut plots = Plots::new();
+        plots.add_records(&[
+            record("28db40f", 10.0, 1, DuplicatePolicy::Average),
+            record("28db40f", 20.0, 2, DuplicatePolicy::Average),
+        ]);
+        let points = line(&plots);
+        assert_eq!(points.len(), 1);
+        let point = &points[0];
+        assert_eq!(
+            (point.y, point.lower, point.upper),
+            (15.0, Some(14.0), Some(16.0))
+        );
+        assert_eq!((point.runs, point.seq), (2, 1));
+
+        plots.add_records(&[record("28db40f", 30.0, 3, DuplicatePolicy::Average)]);
+        let point = &line(&plots)[0];
+        assert_eq!((point.y, point.runs, point.seq), (20.0, 3, 1));
+    }
+
+    #[test]
+    fn converts_records_to_the_plot_unit() {
+        let mut plots = Plots::new();
+        let mut micros = record("4f8ab2c", 2.0, 2, DuplicatePolicy::Replace);
+        micros.unit = "us".into();
+        plots.add_records(&[record("28db40f", 10.0, 1, DuplicatePolicy::Replace), micros]);
+        assert_eq!(plots.get("Fibonacci").unwrap().unit, "ns");
+        let values = line(&plots).iter().map(|p| p.y).collect::<Vec<_>>();
+        assert_eq!(values, [10.0, 2000.0]);
+    }
+
+    fn palette_color(label: &str) -> RGBColor {
+        let (r, g, b) = Palette99::COLORS[stable_hash(label) as usize % Palette99::COLORS.len()];
+        RGBColor(r, g, b)
+    }
+
+    #[test]
+    fn keeps_line_colors_across_runs() {
+        // Pinned, as colors only stay the same across runs while the hash does
+        assert_eq!(stable_hash(""), 0xcbf29ce484222325);
+        assert_eq!(stable_hash("rc=100"), stable_hash("rc=100"));
+        assert_ne!(stable_hash("rc=100"), stable_hash("rc=200"));
+
+        let colors = line_colors(&["rc=100", "rc=200"], &[]);
+        assert_eq!(colors[0], palette_color("rc=100"));
+        let reversed = line_colors(&["rc=200", "rc=100"], &[]);
+        assert_eq!((reversed[0], reversed[1]), (colors[1], colors[0]));
+        assert_eq!(line_colors(&["rc=200"], &[])[0], colors[1]);
+    }
+
+    #[test]
+    fn colliding_lines_take_the_next_free_color() {
+        let colliding = (1..)
+            .map(|i| format!("rc={i}"))
+            .find(|label| palette_color(label) == palette_color("rc=0"))
+            .unwrap();
+        let colors = line_colors(&["rc=0", &colliding], &[]);
+        let start = stable_hash("rc=0") as usize % Palette99::COLORS.len();
+        let (r, g, b) = Palette99::COLORS[(start + 1) % Palette99::COLORS.len()];
+        assert_eq!(colors, [palette_color("rc=0"), RGBColor(r, g, b)]);
+    }
+
+    #[test]
+    fn configured_line_colors_win() {
+        let configured = [
+            parse_line_color("rc=100=#ff0000").unwrap(),
+            // Takes the hashed color of `rc=200`, which moves on to the next free one
+            LineColor {
+                line: "rc=300".into(),
+                color: palette_color("rc=200"),
+            },
+        ];
+        let colors = line_colors(&["rc=100", "rc=200", "rc=300"], &configured);
+        assert_eq!(colors[0], RGBColor(255, 0, 0));
+        assert_ne!(colors[1], palette_color("rc=200"));
+        assert_eq!(colors[2], palette_color("rc=200"));
+        assert!(parse_line_color("rc=100=#ff00").is_err());
+        assert!(parse_line_color("rc=100").is_err());
     }
 }
@@ -0,0 +1,622 @@
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io,
+    io::{Read, Seek, SeekFrom, Write},
+    time::Duration,
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use rusqlite::{params, params_from_iter, types::Value, Connection, Transaction};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::history::{read_history, write_history, ArchiveRecord, History, GENERATOR};
+use crate::params::{split_group, ParamFilter, Params};
+use crate::plot::{Plots, Record, RenderOptions};
+
+// Version of the JSONL log and SQLite database formats. Both keep records as JSON, so fields
+// added with a serde default don't need a new version
+pub const LOG_VERSION: u64 = 1;
+
+// How the history of benchmark results is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StoreKind {
+    /// One JSON file of all plots, rewritten by every run
+    Json,
+    /// An append-only log with one JSON point per line, which runs only add lines to
+    Jsonl,
+    /// An SQLite database, which runs add points to in a transaction
+    Sqlite,
+}
+
+impl StoreKind {
+    // Picks the kind from the file extension, e.g. `plot-data.jsonl`
+    fn from_path(path: &Utf8Path) -> Self {
+        match path.extension() {
+            Some("jsonl") => Self::Jsonl,
+            Some("db" | "sqlite" | "sqlite3") => Self::Sqlite,
+            _ => Self::Json,
+        }
+    }
+}
+
+// Which of the stored points to read
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    // Groups by name, e.g. `Fibonacci-num=10`, or by base name, e.g. `Fibonacci`. All if empty
+    pub groups: Vec<String>,
+    // Lines must match one of the values given for each key, as with `--filter`
+    pub filter: Vec<ParamFilter>,
+    // Only points of commits from this date on
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl Query {
+    // The points that can be drawn with `options`. The `--last` window depends on the commits
+    // of each plot, so it's applied when rendering
+    pub fn new(options: &RenderOptions) -> Self {
+        Self {
+            groups: options.group.clone(),
+            filter: options.filter.clone(),
+            since: options.since,
+        }
+    }
+
+    pub fn matches(&self, group: &str, params: &str, date: DateTime<Utc>) -> bool {
+        let group_matches = |g: &String| g == group || g == split_group(group).0;
+        (self.groups.is_empty() || self.groups.iter().any(group_matches))
+            && self.since.map_or(true, |since| date >= since)
+            && (self.filter.is_empty() || Params::parse(params).matches(&self.filter))
+    }
+}
+
+// Where the history is kept. Every ingestion run adds its records, which are merged into plots in
+// the order they were added when read back
+pub trait Store {
+    // The plots of the records matching `query`. Fails with a `NotFound` IO error if there's no
+    // history yet
+    fn read(&self, query: &Query) -> Result<Plots, Error>;
+
+    // Adds records to the history, creating it if there's none yet
+    fn append(&self, records: &[Record]) -> Result<(), Error>;
+
+    // Replaces the whole history, e.g. when it's rebuilt from archives
+    fn replace(
+        &self,
+        archives: BTreeMap<String, ArchiveRecord>,
+        records: &[Record],
+    ) -> Result<(), Error>;
+
+    // The number of the next ingestion run. Fails like `read` if there's no history yet
+    fn next_seq(&self) -> Result<u64, Error> {
+        self.read(&Query::default()).map(|plots| plots.next_seq())
+    }
+}
+
+pub fn open_store(path: &Utf8Path, kind: Option<StoreKind>) -> Box<dyn Store> {
+    let path = path.to_owned();
+    match kind.unwrap_or_else(|| StoreKind::from_path(&path)) {
+        StoreKind::Json => Box::new(JsonStore { path }),
+        StoreKind::Jsonl => Box::new(JsonlStore { path }),
+        StoreKind::Sqlite => Box::new(SqliteStore { path }),
+    }
+}
+
+// The `plot-data.json` history, read and written in full
+struct JsonStore {
+    path: Utf8PathBuf,
+}
+
+impl Store for JsonStore {
+    fn read(&self, query: &Query) -> Result<Plots, Error> {
+        Ok(read_history(&self.path)?.plots.matching(query))
+    }
+
+    fn append(&self, records: &[Record]) -> Result<(), Error> {
+        let mut history = match read_history(&self.path) {
+            Ok(history) => history,
+            Err(e) if e.is_not_found() => History::new(Plots::new()),
+            Err(e) => return Err(e),
+        };
+        history.plots.add_records(records);
+        write_history(&self.path, &mut history)
+    }
+
+    fn replace(
+        &self,
+        archives: BTreeMap<String, ArchiveRecord>,
+        records: &[Record],
+    ) -> Result<(), Error> {
+        let mut history = History::new(Plots::new());
+        history.metadata.archives = archives;
+        history.plots.add_records(records);
+        write_history(&self.path, &mut history)
+    }
+}
+
+// A line of the JSONL log
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Entry {
+    // The first line, written when the log is created
+    Header {
+        version: u64,
+        generator: String,
+        created: DateTime<Utc>,
+    },
+    // An archive a rebuild read points from
+    Archive {
+        name: String,
+        #[serde(flatten)]
+        record: ArchiveRecord,
+    },
+    Point(Record),
+}
+
+// A log that runs only ever append lines to, so concurrent runs don't overwrite each other's
+// results and the file only grows by the new points
+struct JsonlStore {
+    path: Utf8PathBuf,
+}
+
+impl JsonlStore {
+    fn header() -> Entry {
+        Entry::Header {
+            version: LOG_VERSION,
+            generator: GENERATOR.to_owned(),
+            created: Utc::now(),
+        }
+    }
+
+    fn line(&self, entry: &Entry) -> Result<String, Error> {
+        let json = serde_json::to_string(entry).map_err(|e| Error::serde(&self.path, e))?;
+        Ok(format!("{json}\n"))
+    }
+
+    fn entries(&self) -> Result<Vec<Entry>, Error> {
+        let log = std::fs::read_to_string(&self.path).map_err(|e| Error::io(&self.path, e))?;
+        let mut entries = vec![];
+        for (i, line) in log.split_inclusive('\n').enumerate() {
+            // A last line without a newline is still being appended by another run
+            if !line.ends_with('\n') {
+                break;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            // E.g. the rest of an append that was interrupted, which the next one starts a new
+            // line after. Only that entry is lost, not the whole history
+            let entry = match serde_json::from_str(line) {
+                Ok(entry) => entry,
+                Err(source) => {
+                    let error = Error::LogEntry {
+                        path: self.path.clone(),
+                        line: i + 1,
+                        source,
+                    };
+                    eprintln!("Skipped: {error}");
+                    continue;
+                }
+            };
+            if let Entry::Header { version, .. } = entry {
+                if version > LOG_VERSION {
+                    return Err(Error::HistoryVersion {
+                        path: self.path.clone(),
+                        version,
+                        supported: LOG_VERSION,
+                    });
+                }
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+impl Store for JsonlStore {
+    fn read(&self, query: &Query) -> Result<Plots, Error> {
+        let records = self
+            .entries()?
+            .into_iter()
+            .filter_map(|entry| match entry {
+                Entry::Point(record) => Some(record),
+                Entry::Header { .. } | Entry::Archive { .. } => None,
+            })
+            .filter(|record| query.matches(&record.group, &record.params, record.point.x))
+            .collect::<Vec<_>>();
+        let mut plots = Plots::new();
+        plots.add_records(&records);
+        Ok(plots)
+    }
+
+    fn append(&self, records: &[Record]) -> Result<(), Error> {
+        // Only the run that creates the log writes the header, so concurrent first runs don't
+        // both add one
+        let created = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(&self.path);
+        let (mut file, mut lines) = match created {
+            Ok(file) => (file, self.line(&Self::header())?),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let mut file = OpenOptions::new()
+                    .read(true)
+                    .append(true)
+                    .open(&self.path)
+                    .map_err(|e| Error::io(&self.path, e))?;
+                let mut lines = String::new();
+                // An interrupted append leaves a partial last line, which mustn't swallow the
+                // first new one
+                if file.metadata().map_err(|e| Error::io(&self.path, e))?.len() > 0 {
+                    let mut last = [0];
+                    file.seek(SeekFrom::End(-1))
+                        .and_then(|_| file.read_exact(&mut last))
+                        .map_err(|e| Error::io(&self.path, e))?;
+                    if last != *b"\n" {
+                        lines.push('\n');
+                    }
+                }
+                (file, lines)
+            }
+            Err(e) => return Err(Error::io(&self.path, e)),
+        };
+        for record in records {
+            lines.push_str(&self.line(&Entry::Point(record.clone()))?);
+        }
+        // In a single write, so the lines of concurrent runs don't interleave
+        file.write_all(lines.as_bytes())
+            .map_err(|e| Error::io(&self.path, e))
+    }
+
+    fn replace(
+        &self,
+        archives: BTreeMap<String, ArchiveRecord>,
+        records: &[Record],
+    ) -> Result<(), Error> {
+        let mut lines = self.line(&Self::header())?;
+        for (name, record) in archives {
+            lines.push_str(&self.line(&Entry::Archive { name, record })?);
+        }
+        for record in records {
+            lines.push_str(&self.line(&Entry::Point(record.clone()))?);
+        }
+        std::fs::write(&self.path, lines).map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+// Points are kept as the JSON of their record, next to the columns they're queried by
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS metadata (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS archives (name TEXT PRIMARY KEY, record TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS points (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        group_name TEXT NOT NULL,
+        params TEXT NOT NULL,
+        date INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        record TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS points_group_date ON points (group_name, date);
+";
+
+// An SQLite database that runs add points to in a transaction, so concurrent runs wait for each
+// other instead of overwriting each other's results
+struct SqliteStore {
+    path: Utf8PathBuf,
+}
+
+impl SqliteStore {
+    fn error(&self) -> impl Fn(rusqlite::Error) -> Error + '_ {
+        |source| Error::Database {
+            path: self.path.clone(),
+            source,
+        }
+    }
+
+    // Opens the database, creating it if `create` is set and failing with a `NotFound` IO error
+    // otherwise
+    fn connect(&self, create: bool) -> Result<Connection, Error> {
+        if !create {
+            std::fs::metadata(&self.path).map_err(|e| Error::io(&self.path, e))?;
+        }
+        let conn = Connection::open(&self.path).map_err(self.error())?;
+        conn.busy_timeout(Duration::from_secs(60))
+            .map_err(self.error())?;
+        let version: i64 = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .map_err(self.error())?;
+        if version as u64 > LOG_VERSION {
+            return Err(Error::HistoryVersion {
+                path: self.path.clone(),
+                version: version as u64,
+                supported: LOG_VERSION,
+            });
+        }
+        if version == 0 {
+            conn.execute_batch(SCHEMA).map_err(self.error())?;
+            conn.pragma_update(None, "user_version", LOG_VERSION as i64)
+                .map_err(self.error())?;
+        }
+        Ok(conn)
+    }
+
+    fn insert_records(&self, tx: &Transaction, records: &[Record]) -> Result<(), Error> {
+        let mut insert = tx
+            .prepare(
+                "INSERT INTO points (group_name, params, date, seq, record)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )
+            .map_err(self.error())?;
+        for record in records {
+            let json = serde_json::to_string(record).map_err(|e| Error::serde(&self.path, e))?;
+            insert
+                .execute(params![
+                    record.group,
+                    record.params,
+                    record.point.x.timestamp(),
+                    record.point.seq() as i64,
+                    json
+                ])
+                .map_err(self.error())?;
+        }
+        Ok(())
+    }
+
+    // Stamps the database with this plotter's version, and its creation date if it's new
+    fn touch(&self, tx: &Transaction) -> Result<(), Error> {
+        let now = Utc::now().to_rfc3339();
+        tx.execute(
+            "INSERT OR IGNORE INTO metadata (key, value) VALUES ('created', ?1)",
+            [&now],
+        )
+        .map_err(self.error())?;
+        tx.execute(
+            "INSERT OR REPLACE INTO metadata (key, value)
+             VALUES ('updated', ?1), ('generator', ?2)",
+            [&now, GENERATOR],
+        )
+        .map_err(self.error())?;
+        Ok(())
+    }
+}
+
+impl Store for SqliteStore {
+    fn read(&self, query: &Query) -> Result<Plots, Error> {
+        let conn = self.connect(false)?;
+        // Narrowed down by group and date in SQL, then matched exactly, e.g. by params
+        let mut sql = "SELECT record FROM points WHERE date >= ?1".to_owned();
+        let mut values = vec![Value::Integer(
+            query.since.map_or(i64::MIN, |since| since.timestamp()),
+        )];
+        if !query.groups.is_empty() {
+            let groups = (2..query.groups.len() + 2)
+                .map(|i| format!("group_name = ?{i} OR group_name LIKE ?{i} || '-%'"))
+                .collect::<Vec<_>>();
+            sql.push_str(&format!(" AND ({})", groups.join(" OR ")));
+            values.extend(query.groups.iter().cloned().map(Value::Text));
+        }
+        sql.push_str(" ORDER BY id");
+
+        let mut select = conn.prepare(&sql).map_err(self.error())?;
+        let rows = select
+            .query_map(params_from_iter(values), |row| row.get::<_, String>(0))
+            .map_err(self.error())?;
+        let mut records = vec![];
+        for json in rows {
+            let json = json.map_err(self.error())?;
+            let record: Record = serde_json::from_str(&json).map_err(|source| Error::History {
+                path: self.path.clone(),
+                source,
+            })?;
+            if query.matches(&record.group, &record.params, record.point.x) {
+                records.push(record);
+            }
+        }
+        let mut plots = Plots::new();
+        plots.add_records(&records);
+        Ok(plots)
+    }
+
+    fn append(&self, records: &[Record]) -> Result<(), Error> {
+        let mut conn = self.connect(true)?;
+        let tx = conn.transaction().map_err(self.error())?;
+        self.insert_records(&tx, records)?;
+        self.touch(&tx)?;
+        tx.commit().map_err(self.error())
+    }
+
+    fn replace(
+        &self,
+        archives: BTreeMap<String, ArchiveRecord>,
+        records: &[Record],
+    ) -> Result<(), Error> {
+        let mut conn = self.connect(true)?;
+        // Cleared in the same transaction, so concurrent runs see either the old or the new
+        // history rather than none
+        let tx = conn.transaction().map_err(self.error())?;
+        tx.execute_batch("DELETE FROM points; DELETE FROM archives; DELETE FROM metadata;")
+            .map_err(self.error())?;
+        for (name, record) in archives {
+            let json = serde_json::to_string(&record).map_err(|e| Error::serde(&self.path, e))?;
+            tx.execute(
+                "INSERT INTO archives (name, record) VALUES (?1, ?2)",
+                [&name, &json],
+            )
+            .map_err(self.error())?;
+        }
+        self.insert_records(&tx, records)?;
+        self.touch(&tx)?;
+        tx.commit().map_err(self.error())
+    }
+
+    fn next_seq(&self) -> Result<u64, Error> {
+        let conn = self.connect(false)?;
+        let seq: i64 = conn
+            .query_row("SELECT COALESCE(MAX(seq), 0) + 1 FROM points", [], |row| {
+                row.get(0)
+            })
+            .map_err(self.error())?;
+        Ok(seq as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(group: &str, params: &str, date: &str, commit: &str, seq: u64) -> Record {
+        serde_json::from_value(serde_json::json!({
+            "group": group,
+            "params": params,
+            "unit": "ns",
+            "x": date,
+            "y": 10.0,
+            "commit": commit,
+            "seq": seq,
+        }))
+        .unwrap()
+    }
+
+    fn commits(plots: &Plots, group: &str) -> Vec<String> {
+        let plot = plots.get(group).unwrap();
+        plot.lines
+            .values()
+            .flatten()
+            .filter_map(|p| p.commit.clone())
+            .collect()
+    }
+
+    fn stores(dir: &tempfile::TempDir) -> Vec<Box<dyn Store>> {
+        ["plot-data.json", "plot-data.jsonl", "plot-data.db"]
+            .into_iter()
+            .map(|name| {
+                let path = Utf8PathBuf::from_path_buf(dir.path().join(name)).unwrap();
+                open_store(&path, None)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn appends_after_a_partial_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::from_path_buf(dir.path().join("plot-data.jsonl")).unwrap();
+        let store = open_store(&path, None);
+        store
+            .append(&[record("Fib", "", "2024-01-01T00:00:00Z", "aaaaaaa", 1)])
+            .unwrap();
+        // An append that was interrupted halfway through its line
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"type":"point","group":"Fib","#)
+            .unwrap();
+        store
+            .append(&[record("Fib", "", "2024-01-02T00:00:00Z", "bbbbbbb", 2)])
+            .unwrap();
+
+        let plots = store.read(&Query::default()).unwrap();
+        assert_eq!(commits(&plots, "Fib"), ["aaaaaaa", "bbbbbbb"]);
+        assert_eq!(store.next_seq().unwrap(), 3);
+    }
+
+    #[test]
+    fn missing_histories_are_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        for store in stores(&dir) {
+            assert!(store.next_seq().unwrap_err().is_not_found());
+            assert!(store.read(&Query::default()).unwrap_err().is_not_found());
+        }
+    }
+
+    #[test]
+    fn replace_discards_earlier_records() {
+        let dir = tempfile::tempdir().unwrap();
+        for store in stores(&dir) {
+            store
+                .append(&[record("Fib", "", "2024-01-01T00:00:00Z", "aaaaaaa", 1)])
+                .unwrap();
+            for _ in 0..2 {
+                store
+                    .replace(
+                        BTreeMap::new(),
+                        &[
+                            record("Fib", "", "2024-01-02T00:00:00Z", "bbbbbbb", 1),
+                            record("Fib", "", "2024-01-03T00:00:00Z", "ccccccc", 2),
+                        ],
+                    )
+                    .unwrap();
+            }
+            let plots = store.read(&Query::default()).unwrap();
+            assert_eq!(commits(&plots, "Fib"), ["bbbbbbb", "ccccccc"]);
+            assert_eq!(store.next_seq().unwrap(), 3);
+        }
+    }
+
+    #[test]
+    fn queries_by_base_group_and_date() {
+        let dir = tempfile::tempdir().unwrap();
+        for store in stores(&dir) {
+            store
+                .append(&[
+                    record(
+                        "Fibonacci-num=10",
+                        "rc=100",
+                        "2024-01-01T00:00:00Z",
+                        "aaaaaaa",
+                        1,
+                    ),
+                    record(
+                        "Fibonacci-num=20",
+                        "rc=100",
+                        "2024-01-02T00:00:00Z",
+                        "bbbbbbb",
+                        2,
+                    ),
+                    record(
+                        "Fibonacci-num=20",
+                        "rc=200",
+                        "2024-01-03T00:00:00Z",
+                        "ccccccc",
+                        3,
+                    ),
+                    // Shares a prefix with the queried group, but has another base name
+                    record(
+                        "FibonacciX-num=10",
+                        "rc=100",
+                        "2024-01-03T00:00:00Z",
+                        "ccccccc",
+                        3,
+                    ),
+                ])
+                .unwrap();
+
+            let query = Query {
+                groups: vec!["Fibonacci".into()],
+                ..Query::default()
+            };
+            let plots = store.read(&query).unwrap();
+            let groups = plots
+                .iter()
+                .map(|(group, _)| group.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(groups, ["Fibonacci-num=10", "Fibonacci-num=20"]);
+
+            let query = Query {
+                groups: vec!["Fibonacci-num=20".into()],
+                filter: vec!["rc=200".parse().unwrap()],
+                since: Some("2024-01-02T00:00:00Z".parse().unwrap()),
+            };
+            let plots = store.read(&query).unwrap();
+            assert_eq!(plots.iter().count(), 1);
+            assert_eq!(commits(&plots, "Fibonacci-num=20"), ["ccccccc"]);
+
+            let query = Query {
+                since: Some("2024-01-02T00:00:00Z".parse().unwrap()),
+                ..Query::default()
+            };
+            let plots = store.read(&query).unwrap();
+            assert!(plots.get("Fibonacci-num=10").is_none());
+        }
+    }
+}
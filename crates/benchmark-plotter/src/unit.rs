@@ -0,0 +1,158 @@
+// Time units a Criterion estimate can be reported in, from smallest to largest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl TimeUnit {
+    const ALL: [TimeUnit; 4] = [
+        TimeUnit::Nanoseconds,
+        TimeUnit::Microseconds,
+        TimeUnit::Milliseconds,
+        TimeUnit::Seconds,
+    ];
+
+    // Returns `None` for non-time measurements, e.g. cycles or bytes
+    pub fn parse(unit: &str) -> Option<Self> {
+        match unit {
+            "ns" => Some(TimeUnit::Nanoseconds),
+            "us" | "µs" | "μs" => Some(TimeUnit::Microseconds),
+            "ms" => Some(TimeUnit::Milliseconds),
+            "s" => Some(TimeUnit::Seconds),
+            _ => None,
+        }
+    }
+
+    pub const fn symbol(self) -> &'static str {
+        match self {
+            TimeUnit::Nanoseconds => "ns",
+            TimeUnit::Microseconds => "µs",
+            TimeUnit::Milliseconds => "ms",
+            TimeUnit::Seconds => "s",
+        }
+    }
+
+    const fn nanos(self) -> f64 {
+        match self {
+            TimeUnit::Nanoseconds => 1.0,
+            TimeUnit::Microseconds => 1e3,
+            TimeUnit::Milliseconds => 1e6,
+            TimeUnit::Seconds => 1e9,
+        }
+    }
+
+    // Factor to multiply a value in `self` by to express it in `other`
+    pub fn factor_to(self, other: TimeUnit) -> f64 {
+        self.nanos() / other.nanos()
+    }
+
+    // The largest unit in which `value` (given in `self`) is still at least 1
+    pub fn readable(self, value: f64) -> TimeUnit {
+        let nanos = value.abs() * self.nanos();
+        Self::ALL
+            .into_iter()
+            .rev()
+            .find(|unit| nanos >= unit.nanos())
+            .unwrap_or(TimeUnit::Nanoseconds)
+    }
+}
+
+// Factor to multiply a value in unit `from` by to express it in `to`. Values are left as they are
+// unless both are time units, e.g. for cycles or bytes
+pub fn factor(from: &str, to: &str) -> f64 {
+    match (TimeUnit::parse(from), TimeUnit::parse(to)) {
+        (Some(from), Some(to)) => from.factor_to(to),
+        _ => 1.0,
+    }
+}
+
+// How to display the values of a plot: the factor to scale them by and the resulting unit label
+#[derive(Debug, Clone)]
+pub struct Scale {
+    pub factor: f64,
+    pub unit: String,
+}
+
+impl Scale {
+    // Picks a readable unit for values in `unit` up to `max`. Units that aren't time units are
+    // left unscaled
+    pub fn readable(unit: &str, max: f64) -> Self {
+        match TimeUnit::parse(unit) {
+            Some(time_unit) => {
+                let readable = time_unit.readable(max);
+                Self {
+                    factor: time_unit.factor_to(readable),
+                    unit: readable.symbol().to_owned(),
+                }
+            }
+            None => Self {
+                factor: 1.0,
+                unit: unit.to_owned(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_largest_unit_of_at_least_one() {
+        let cases = [
+            (TimeUnit::Nanoseconds, 0.5, TimeUnit::Nanoseconds),
+            (TimeUnit::Nanoseconds, 0.0, TimeUnit::Nanoseconds),
+            (TimeUnit::Nanoseconds, 999.9, TimeUnit::Nanoseconds),
+            (TimeUnit::Nanoseconds, 1000.0, TimeUnit::Microseconds),
+            (TimeUnit::Nanoseconds, -1500.0, TimeUnit::Microseconds),
+            (TimeUnit::Microseconds, 1000.0, TimeUnit::Milliseconds),
+            (TimeUnit::Milliseconds, 0.5, TimeUnit::Microseconds),
+            (TimeUnit::Seconds, 0.0005, TimeUnit::Microseconds),
+            (TimeUnit::Seconds, 120.0, TimeUnit::Seconds),
+        ];
+        for (unit, value, expected) in cases {
+            assert_eq!(unit.readable(value), expected, "{value} {}", unit.symbol());
+        }
+    }
+
+    #[test]
+    fn converts_between_time_units_only() {
+        let cases = [
+            ("ms", "ns", 1e6),
+            ("ns", "ms", 1e-6),
+            ("s", "us", 1e6),
+            ("us", "µs", 1.0),
+            ("μs", "ns", 1e3),
+            ("cycles", "ns", 1.0),
+            ("ns", "bytes", 1.0),
+            ("cycles", "cycles", 1.0),
+        ];
+        for (from, to, expected) in cases {
+            assert_eq!(factor(from, to), expected, "{from} -> {to}");
+        }
+    }
+
+    #[test]
+    fn scales_time_units_and_leaves_others() {
+        let cases = [
+            ("ns", 0.5, 1.0, "ns"),
+            ("ns", 1000.0, 1e-3, "µs"),
+            ("ns", 2.5e9, 1e-9, "s"),
+            ("us", 999.0, 1.0, "µs"),
+            ("ms", 0.2, 1e3, "µs"),
+            ("cycles", 1e9, 1.0, "cycles"),
+            ("bytes", 0.5, 1.0, "bytes"),
+        ];
+        for (unit, max, factor, scaled) in cases {
+            let scale = Scale::readable(unit, max);
+            assert_eq!(
+                (scale.factor, scale.unit.as_str()),
+                (factor, scaled),
+                "{max} {unit}"
+            );
+        }
+    }
+}